@@ -0,0 +1,164 @@
+use crate::keymap;
+use crate::keystate::{KeyEvent, LedMask, Modifiers};
+use crate::xt::XtBytes;
+
+// Change to suit the terminal. Bits are timed to the nearest 10us without
+// adding up the error, so anything up to 9600 works.
+pub const BAUD: u32 = 9600;
+
+// Turns key presses into ASCII, and keys without a character into the escape
+// sequences of an ANSI/VT100 terminal. Releases send nothing.
+pub struct Encoder;
+
+impl Encoder {
+    pub const OVERRUN: u8 = 0x07; // BEL
+    const ESC: u8 = 0x1b;
+
+    // Most bytes a single key event sends: the F5-F12 escape sequences.
+    pub const LONGEST: u8 = 5;
+
+    // Keypad keys that are cursor keys with Num Lock off. Their Set 2 codes
+    // are the same as those of the cursor keys, without E0.
+    const KEYPAD_CURSOR: [u8; 10] = [0x69, 0x6b, 0x6c, 0x70, 0x71, 0x72, 0x74, 0x75, 0x7a, 0x7d];
+
+    pub const fn new() -> Encoder {
+        Encoder
+    }
+
+    pub fn encode(&mut self, ev: KeyEvent, mods: Modifiers, locks: LedMask) -> XtBytes {
+        if !ev.pressed || ev.is_pause() {
+            return XtBytes::none();
+        }
+
+        let cursor =
+            ev.extended || (!locks.contains(LedMask::NUM) && Self::KEYPAD_CURSOR.contains(&ev.key));
+
+        if cursor {
+            return keymap::to_ansi(ev.key, true).map_or(XtBytes::none(), XtBytes::text);
+        }
+
+        let ch = match keymap::to_ascii(ev.key) {
+            Some((plain, shifted)) => {
+                // Caps Lock only affects letters, and Shift undoes it.
+                let caps = locks.contains(LedMask::CAPS) && plain.is_ascii_lowercase();
+
+                if mods.shift() == caps {
+                    plain
+                } else {
+                    shifted
+                }
+            }
+            None => return keymap::to_ansi(ev.key, false).map_or(XtBytes::none(), XtBytes::text),
+        };
+
+        let ch = if mods.ctrl() && (0x40..=0x7f).contains(&ch) {
+            ch & 0x1f
+        } else {
+            ch
+        };
+
+        // Meta, the way xterm sends it.
+        if mods.alt() {
+            XtBytes::two(Self::ESC, ch)
+        } else {
+            XtBytes::one(ch)
+        }
+    }
+
+    // A terminal has no keys to be left holding down.
+    pub fn reset(&mut self) {}
+
+    pub fn release_all(&mut self) -> core::iter::Empty<u8> {
+        core::iter::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONE: Modifiers = Modifiers::empty();
+    const UNLOCKED: LedMask = LedMask::empty();
+    const A: u8 = 0x1c;
+    const ONE: u8 = 0x16;
+
+    fn press(key: u8, extended: bool, mods: Modifiers, locks: LedMask) -> Vec<u8> {
+        let ev = KeyEvent {
+            key,
+            pressed: true,
+            extended,
+        };
+
+        Encoder::new().encode(ev, mods, locks).collect()
+    }
+
+    fn typed(key: u8, mods: Modifiers, locks: LedMask) -> Vec<u8> {
+        press(key, false, mods, locks)
+    }
+
+    #[test]
+    fn no_key_sends_more_than_longest() {
+        for key in 0..=0xff {
+            for extended in [false, true] {
+                let n = press(key, extended, Modifiers::all(), LedMask::empty()).len();
+                assert!(n <= usize::from(Encoder::LONGEST), "{:#x} sent {}", key, n);
+            }
+        }
+    }
+
+    #[test]
+    fn shift_and_caps_lock() {
+        assert_eq!(typed(A, NONE, UNLOCKED), b"a");
+        assert_eq!(typed(A, Modifiers::RSHIFT, UNLOCKED), b"A");
+        assert_eq!(typed(A, NONE, LedMask::CAPS), b"A");
+        assert_eq!(typed(A, Modifiers::LSHIFT, LedMask::CAPS), b"a");
+        // Caps Lock leaves everything but letters alone.
+        assert_eq!(typed(ONE, NONE, LedMask::CAPS), b"1");
+        assert_eq!(typed(ONE, Modifiers::LSHIFT, LedMask::CAPS), b"!");
+    }
+
+    #[test]
+    fn ctrl_makes_control_characters() {
+        assert_eq!(typed(A, Modifiers::LCTRL, UNLOCKED), [0x01]);
+        assert_eq!(
+            typed(A, Modifiers::RCTRL | Modifiers::LSHIFT, UNLOCKED),
+            [0x01]
+        );
+        assert_eq!(typed(ONE, Modifiers::LCTRL, UNLOCKED), b"1");
+    }
+
+    #[test]
+    fn alt_prefixes_escape() {
+        assert_eq!(typed(A, Modifiers::LALT, UNLOCKED), b"\x1ba");
+        assert_eq!(
+            typed(A, Modifiers::RALT | Modifiers::LCTRL, UNLOCKED),
+            [0x1b, 0x01]
+        );
+    }
+
+    #[test]
+    fn releases_send_nothing() {
+        let ev = KeyEvent {
+            key: A,
+            pressed: false,
+            extended: false,
+        };
+
+        assert_eq!(Encoder::new().encode(ev, NONE, UNLOCKED).count(), 0);
+    }
+
+    #[test]
+    fn keypad_is_cursor_keys_without_num_lock() {
+        let keypad_4 = 0x6b;
+
+        assert_eq!(typed(keypad_4, NONE, LedMask::NUM), b"4");
+        assert_eq!(typed(keypad_4, NONE, UNLOCKED), b"\x1b[D");
+        assert_eq!(press(keypad_4, true, NONE, LedMask::NUM), b"\x1b[D");
+    }
+
+    #[test]
+    fn function_keys_send_escapes() {
+        assert_eq!(typed(0x05, NONE, UNLOCKED), b"\x1bOP");
+        assert_eq!(typed(0x07, NONE, UNLOCKED), b"\x1b[24~");
+    }
+}
@@ -0,0 +1,386 @@
+use crate::keybuffer::{KeyIn, KeyOut, Keycode};
+
+// What the keyboard side of an AT link does to the XT port lines next. Both
+// lines are open drain: true releases the line, false pulls it low.
+pub struct AtStep {
+    pub clk: bool,
+    pub data: bool,
+    pub wait: Option<u16>,
+    pub received: Option<Keycode>,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Idle,
+    Send {
+        byte: u8,
+        out: KeyOut,
+        data: bool,
+        clk_low: bool,
+        started: bool,
+    },
+    Recv {
+        key_in: KeyIn,
+        clk_low: bool,
+    },
+    Ack {
+        clk_low: bool,
+    },
+}
+
+// Plays the keyboard towards an AT host, clocking bytes both ways with the
+// same framing KeyIn and KeyOut use towards the real keyboard.
+#[derive(Clone, Copy)]
+pub struct AtLink {
+    state: State,
+    pending: Option<u8>, // A byte the host interrupted; sent again in full.
+}
+
+impl AtLink {
+    // Half a clock period, in 10us timer ticks; a 12.5kHz clock.
+    const HALF: u16 = 4;
+    const POLL: u16 = 10;
+
+    pub const fn new() -> AtLink {
+        AtLink {
+            state: State::Idle,
+            pending: None,
+        }
+    }
+
+    // clk and data are the levels on the lines right now. next() supplies
+    // the next byte for the host, if any.
+    pub fn step<F: FnOnce() -> Option<u8>>(
+        &mut self,
+        clk: bool,
+        data: bool,
+        now: u16,
+        next: F,
+    ) -> AtStep {
+        let (state, step) = match self.state {
+            // The host wants to send: it released CLK but kept DATA low.
+            // That was the start bit.
+            State::Idle if clk && !data => {
+                let mut key_in = KeyIn::new();
+                #[allow(clippy::let_underscore_must_use)]
+                {
+                    let _ = key_in.shift_in(false, now);
+                }
+
+                (
+                    State::Recv {
+                        key_in,
+                        clk_low: true,
+                    },
+                    Self::lines(false, true, None),
+                )
+            }
+            State::Idle => {
+                let byte = self.pending.take().or_else(next);
+
+                match byte {
+                    // Inhibited; hang on to the byte until the host lets go.
+                    Some(b) if !clk => {
+                        self.pending = Some(b);
+                        (State::Idle, Self::released(Some(Self::POLL)))
+                    }
+                    Some(b) => {
+                        let mut out = KeyOut::new();
+                        out.put(b, now).ok();
+
+                        (
+                            State::Send {
+                                byte: b,
+                                out,
+                                data: true,
+                                clk_low: false,
+                                started: false,
+                            },
+                            Self::released(Some(Self::HALF)),
+                        )
+                    }
+                    None => (State::Idle, Self::released(None)),
+                }
+            }
+            State::Send {
+                byte,
+                out,
+                data,
+                clk_low: true,
+                started,
+            } => (
+                State::Send {
+                    byte,
+                    out,
+                    data,
+                    clk_low: false,
+                    started,
+                },
+                Self::lines(true, data, None),
+            ),
+            // The host held CLK low after we let go of it, aborting the byte.
+            State::Send { byte, .. } if !clk => {
+                self.pending = Some(byte);
+                (State::Idle, Self::released(Some(Self::POLL)))
+            }
+            State::Send {
+                byte,
+                mut out,
+                started,
+                ..
+            } => {
+                let bit = if started {
+                    out.shift_out()
+                } else {
+                    Some(false)
+                };
+
+                match bit {
+                    Some(b) => (
+                        State::Send {
+                            byte,
+                            out,
+                            data: b,
+                            clk_low: true,
+                            started: true,
+                        },
+                        Self::lines(false, b, None),
+                    ),
+                    None => (State::Idle, Self::released(Some(Self::HALF))),
+                }
+            }
+            State::Recv {
+                key_in,
+                clk_low: true,
+            } => (
+                State::Recv {
+                    key_in,
+                    clk_low: false,
+                },
+                Self::lines(true, true, None),
+            ),
+            // The host changes DATA while CLK is low; read it while high.
+            State::Recv { mut key_in, .. } => {
+                if key_in.shift_in(data, now).is_err() {
+                    (
+                        State::Ack { clk_low: true },
+                        Self::lines(false, false, key_in.take()),
+                    )
+                } else {
+                    (
+                        State::Recv {
+                            key_in,
+                            clk_low: true,
+                        },
+                        Self::lines(false, true, None),
+                    )
+                }
+            }
+            State::Ack { clk_low: true } => (
+                State::Ack { clk_low: false },
+                Self::lines(true, false, None),
+            ),
+            State::Ack { .. } => (State::Idle, Self::released(Some(Self::HALF))),
+        };
+
+        self.state = state;
+        step
+    }
+
+    fn lines(clk: bool, data: bool, received: Option<Keycode>) -> AtStep {
+        AtStep {
+            clk,
+            data,
+            wait: Some(Self::HALF),
+            received,
+        }
+    }
+
+    fn released(wait: Option<u16>) -> AtStep {
+        AtStep {
+            clk: true,
+            data: true,
+            wait,
+            received: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keybuffer::Status;
+
+    // An AtLink and the two open-drain lines it shares with the host.
+    struct Bus {
+        link: AtLink,
+        clk: bool,
+        data: bool,
+        now: u16,
+    }
+
+    impl Bus {
+        fn new() -> Bus {
+            Bus {
+                link: AtLink::new(),
+                clk: true,
+                data: true,
+                now: 0,
+            }
+        }
+
+        // A line is low if either side pulls it low.
+        fn step(&mut self, host_clk: bool, host_data: bool, next: Option<u8>) -> AtStep {
+            let step = self.link.step(
+                self.clk && host_clk,
+                self.data && host_data,
+                self.now,
+                || next,
+            );
+
+            self.clk = step.clk;
+            self.data = step.data;
+            self.now = self.now.wrapping_add(step.wait.unwrap_or(AtLink::HALF));
+            step
+        }
+
+        // Data at each falling clock edge, with the host letting the link
+        // talk, until it goes quiet.
+        fn listen(&mut self, next: Option<u8>) -> Vec<bool> {
+            let mut next = next;
+            let mut bits = Vec::new();
+
+            for _ in 0..100 {
+                let step = self.step(true, true, next.take());
+
+                if !step.clk {
+                    bits.push(step.data);
+                }
+
+                if step.wait.is_none() {
+                    break;
+                }
+            }
+
+            bits
+        }
+
+        // Clocks a frame in from the host, start bit first. Returns what the
+        // link received and whether it pulled data low to ACK.
+        fn command(&mut self, frame: &[bool]) -> (Option<Keycode>, bool) {
+            let mut received = None;
+            let mut ack = false;
+
+            // The link reads a bit, then lets go of CLK. The host lets go of
+            // DATA after the stop bit, and the link after the ACK.
+            for &bit in frame.iter().chain(&[true]) {
+                for _ in 0..2 {
+                    let step = self.step(true, bit, None);
+                    ack |= !step.clk && !step.data;
+                    received = received.or(step.received);
+                }
+            }
+
+            (received, ack)
+        }
+    }
+
+    // Start bit, data LSB first, odd parity (or not), and stop bit.
+    fn frame(byte: u8, good_parity: bool) -> Vec<bool> {
+        let parity = (byte.count_ones() % 2 == 0) == good_parity;
+
+        let mut bits = vec![false];
+        bits.extend((0..8).map(|i| byte & (1 << i) != 0));
+        bits.push(parity);
+        bits.push(true);
+        bits
+    }
+
+    #[test]
+    fn bytes_go_out_framed_like_a_keyboard() {
+        let mut bus = Bus::new();
+
+        assert_eq!(bus.listen(Some(0x1c)), frame(0x1c, true));
+        assert_eq!(bus.listen(Some(0xaa)), frame(0xaa, true));
+        assert!(bus.clk && bus.data);
+    }
+
+    #[test]
+    fn an_inhibited_host_gets_the_byte_once_it_lets_go() {
+        let mut bus = Bus::new();
+
+        for _ in 0..3 {
+            let step = bus.step(false, true, Some(0x1c));
+            assert!(step.clk && step.data);
+            assert_eq!(step.wait, Some(AtLink::POLL));
+        }
+
+        // The byte waited; the next one can wait its turn.
+        assert_eq!(bus.listen(Some(0x32)), frame(0x1c, true));
+    }
+
+    #[test]
+    fn an_aborted_byte_is_sent_again_in_full() {
+        let mut bus = Bus::new();
+
+        // Send a few bits; the host then holds CLK low while it's released.
+        bus.step(true, true, Some(0x1c));
+        for _ in 0..6 {
+            bus.step(true, true, None);
+        }
+        assert!(bus.clk);
+
+        let step = bus.step(false, true, None);
+        assert!(step.clk && step.data);
+        assert_eq!(step.wait, Some(AtLink::POLL));
+
+        assert_eq!(bus.listen(Some(0x32)), frame(0x1c, true));
+        assert_eq!(bus.listen(None), []);
+    }
+
+    #[test]
+    fn host_commands_are_clocked_in_and_acked() {
+        let mut bus = Bus::new();
+
+        let (received, ack) = bus.command(&frame(0xed, true));
+        let k = received.unwrap();
+        assert_eq!(k.byte, 0xed);
+        assert!(k.is_valid());
+        assert!(ack);
+
+        // Both lines are let go after the ACK bit.
+        assert!(bus.clk && bus.data);
+        assert_eq!(bus.listen(Some(0xfa)), frame(0xfa, true));
+    }
+
+    #[test]
+    fn a_host_command_with_bad_parity_is_flagged() {
+        let mut bus = Bus::new();
+
+        let (received, _) = bus.command(&frame(0xff, false));
+        let k = received.unwrap();
+        assert_eq!(k.byte, 0xff);
+        assert!(k.status.contains(Status::PARITY));
+        assert!(!k.is_valid());
+    }
+
+    #[test]
+    fn a_host_command_can_cut_a_byte_short() {
+        let mut bus = Bus::new();
+
+        bus.step(true, true, Some(0x1c));
+        for _ in 0..4 {
+            bus.step(true, true, None);
+        }
+        bus.step(false, true, None);
+
+        // The host holds CLK low, then takes DATA low and lets go of CLK.
+        bus.step(false, false, None);
+        let (received, ack) = bus.command(&frame(0xff, true));
+        assert_eq!(received.map(|k| k.byte), Some(0xff));
+        assert!(ack);
+
+        // The byte the host cut short goes out after.
+        assert_eq!(bus.listen(None), frame(0x1c, true));
+    }
+}
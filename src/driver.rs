@@ -13,11 +13,119 @@ bitflags! {
         const UNUSED_5 = 0b0010_0000;
         const UNUSED_6 = 0b0100_0000;
         const UNUSED_7 = 0b1000_0000;
+        #[cfg(feature = "tandy1000")]
+        const TANDY_BUSY = Self::UNUSED_5.bits();
+        #[cfg(feature = "ps2-mouse")]
+        const MOUSE_TX = Self::UNUSED_5.bits();
+        #[cfg(feature = "ps2-mouse")]
+        const MOUSE_CLK = Self::UNUSED_6.bits();
+        #[cfg(feature = "ps2-mouse")]
+        const MOUSE_DATA = Self::UNUSED_7.bits();
+        #[cfg(feature = "second-keyboard")]
+        const AT2_CLK = Self::UNUSED_5.bits();
+        #[cfg(feature = "second-keyboard")]
+        const AT2_DATA = Self::UNUSED_6.bits();
+        #[cfg(feature = "kvm")]
+        const XT2_CLK = Self::UNUSED_5.bits();
+        #[cfg(feature = "kvm")]
+        const XT2_DATA = Self::UNUSED_6.bits();
+        #[cfg(feature = "kvm")]
+        const XT2_SENSE = Self::UNUSED_7.bits();
         const AT_MASK = Self::AT_CLK.bits() | Self::AT_DATA.bits();
         const XT_MASK = Self::XT_CLK.bits() | Self::XT_DATA.bits();
     }
 }
 
+// The XT hosts' lines; kvm builds have a second set.
+#[derive(Clone, Copy, PartialEq)]
+pub enum XtPort {
+    First,
+    #[cfg(feature = "kvm")]
+    Second,
+}
+
+impl XtPort {
+    #[cfg(not(feature = "kvm"))]
+    pub const ALL: [XtPort; 1] = [XtPort::First];
+    #[cfg(feature = "kvm")]
+    pub const ALL: [XtPort; 2] = [XtPort::First, XtPort::Second];
+
+    #[cfg(not(any(feature = "pcjr", feature = "ascii", feature = "xt-keyboard")))]
+    pub fn clk(self) -> Pins {
+        match self {
+            XtPort::First => Pins::XT_CLK,
+            #[cfg(feature = "kvm")]
+            XtPort::Second => Pins::XT2_CLK,
+        }
+    }
+
+    #[cfg(not(any(feature = "pcjr", feature = "ascii", feature = "xt-keyboard")))]
+    pub fn data(self) -> Pins {
+        match self {
+            XtPort::First => Pins::XT_DATA,
+            #[cfg(feature = "kvm")]
+            XtPort::Second => Pins::XT2_DATA,
+        }
+    }
+
+    pub fn sense(self) -> Pins {
+        match self {
+            XtPort::First => Pins::XT_SENSE,
+            #[cfg(feature = "kvm")]
+            XtPort::Second => Pins::XT2_SENSE,
+        }
+    }
+
+    // Every port, starting with the one after this one.
+    #[cfg(not(any(
+        feature = "kvm",
+        feature = "pcjr",
+        feature = "ascii",
+        feature = "xt-keyboard"
+    )))]
+    pub fn turns(self) -> [XtPort; 1] {
+        [self]
+    }
+
+    #[cfg(feature = "kvm")]
+    pub fn turns(self) -> [XtPort; 2] {
+        [self.other(), self]
+    }
+
+    #[cfg(feature = "kvm")]
+    pub fn other(self) -> XtPort {
+        match self {
+            XtPort::First => XtPort::Second,
+            XtPort::Second => XtPort::First,
+        }
+    }
+}
+
+#[cfg(not(feature = "kvm"))]
+const XT_SENSES: Pins = Pins::XT_SENSE;
+#[cfg(feature = "kvm")]
+const XT_SENSES: Pins = Pins::XT_SENSE.union(Pins::XT2_SENSE);
+
+// The extra AT port, for a mouse or a second keyboard.
+#[cfg(feature = "ps2-mouse")]
+pub const AUX_CLK: Pins = Pins::MOUSE_CLK;
+#[cfg(feature = "ps2-mouse")]
+pub const AUX_DATA: Pins = Pins::MOUSE_DATA;
+#[cfg(feature = "second-keyboard")]
+pub const AUX_CLK: Pins = Pins::AT2_CLK;
+#[cfg(feature = "second-keyboard")]
+pub const AUX_DATA: Pins = Pins::AT2_DATA;
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+const AUX_MASK: Pins = AUX_CLK.union(AUX_DATA);
+
+// Port 1 is full, so the serial port's RTS comes in on P2.6 (XIN, unused
+// without a crystal). The level shifter inverts it, so RTS coming on is a
+// falling edge.
+#[cfg(feature = "ps2-mouse")]
+const MOUSE_RTS: u8 = 0b0100_0000;
+#[cfg(feature = "ps2-mouse")]
+const XOUT: u8 = 0b1000_0000;
+
 macro_rules! from_impl_for_pins {
     ($t:ty) => {
         impl From<$t> for Pins {
@@ -80,6 +188,7 @@ where
     });
 }
 
+#[cfg(not(feature = "xt-keyboard"))]
 pub fn set(p: &msp430g2211::PORT_1_2, pins: Pins) {
     set_port_reg(&p.p1out, pins);
 }
@@ -88,6 +197,7 @@ pub fn unset(p: &msp430g2211::PORT_1_2, pins: Pins) {
     clear_port_reg(&p.p1out, pins)
 }
 
+#[cfg(not(feature = "xt-keyboard"))]
 pub fn mk_in(p: &msp430g2211::PORT_1_2, pins: Pins) {
     clear_port_reg(&p.p1dir, pins)
 }
@@ -98,23 +208,26 @@ pub fn is_set(p: &msp430g2211::PORT_1_2, pins: Pins) -> bool {
     Pins::from(&p.p1in.read()).contains(pins)
 }
 
+#[cfg(not(feature = "xt-keyboard"))]
 pub fn is_unset(p: &msp430g2211::PORT_1_2, pins: Pins) -> bool {
     !Pins::from(&p.p1in.read()).intersects(pins)
 }
 
 pub fn idle(p: &msp430g2211::PORT_1_2) {
     p.p1dir.write(|w| w.p1dir().bits(0x00));
-    clear_port_reg(&p.p1ifg, Pins::AT_CLK);
-    set_port_reg(&p.p1ies, Pins::AT_CLK);
-    set_port_reg(&p.p1ie, Pins::AT_CLK);
+    clear_port_reg(&p.p1ifg, Pins::AT_CLK | XT_SENSES);
+    set_port_reg(&p.p1ies, Pins::AT_CLK | XT_SENSES);
+    set_port_reg(&p.p1ie, Pins::AT_CLK | XT_SENSES);
 }
 
+#[cfg(not(feature = "xt-keyboard"))]
 pub fn disable_at_clk_int(p: &msp430g2211::PORT_1_2) {
     clear_port_reg(&p.p1ie, Pins::AT_CLK);
 }
 
 // Spurious pin interrupts are undesireable, but should not cause memory
 // safety issues (data races) due to the various Cells.
+#[cfg(not(feature = "xt-keyboard"))]
 pub fn enable_at_clk_int(p: &msp430g2211::PORT_1_2) {
     set_port_reg(&p.p1ie, Pins::AT_CLK);
 }
@@ -123,24 +236,156 @@ pub fn clear_at_clk_int(p: &msp430g2211::PORT_1_2) {
     clear_port_reg(&p.p1ifg, Pins::AT_CLK);
 }
 
+// AT_CLK edges still set the flag while its interrupt is disabled; those
+// don't count.
+pub fn at_clk_int_pending(p: &msp430g2211::PORT_1_2) -> bool {
+    Pins::from(&p.p1ifg.read())
+        .intersection(Pins::from(&p.p1ie.read()))
+        .contains(Pins::AT_CLK)
+}
+
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+pub fn disable_aux_clk_int(p: &msp430g2211::PORT_1_2) {
+    clear_port_reg(&p.p1ie, AUX_CLK);
+}
+
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+pub fn enable_aux_clk_int(p: &msp430g2211::PORT_1_2) {
+    set_port_reg(&p.p1ie, AUX_CLK);
+}
+
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+pub fn clear_aux_clk_int(p: &msp430g2211::PORT_1_2) {
+    clear_port_reg(&p.p1ifg, AUX_CLK);
+}
+
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+pub fn aux_clk_int_pending(p: &msp430g2211::PORT_1_2) -> bool {
+    Pins::from(&p.p1ifg.read())
+        .intersection(Pins::from(&p.p1ie.read()))
+        .contains(AUX_CLK)
+}
+
+pub fn xt_sense_int_pending(p: &msp430g2211::PORT_1_2, xt: XtPort) -> bool {
+    Pins::from(&p.p1ifg.read())
+        .intersection(Pins::from(&p.p1ie.read()))
+        .contains(xt.sense())
+}
+
+pub fn clear_xt_sense_int(p: &msp430g2211::PORT_1_2, xt: XtPort) {
+    clear_port_reg(&p.p1ifg, xt.sense());
+}
+
+// Changing the edge can set the flag by itself; callers go by the level.
+#[cfg(not(feature = "xt-keyboard"))]
+pub fn xt_sense_on_fall(p: &msp430g2211::PORT_1_2, xt: XtPort) {
+    set_port_reg(&p.p1ies, xt.sense());
+}
+
+#[cfg(not(feature = "xt-keyboard"))]
+pub fn xt_sense_on_rise(p: &msp430g2211::PORT_1_2, xt: XtPort) {
+    clear_port_reg(&p.p1ies, xt.sense());
+}
+
+#[cfg(feature = "xt-keyboard")]
+pub fn at_clk_on_rise(p: &msp430g2211::PORT_1_2) {
+    clear_port_reg(&p.p1ies, Pins::AT_CLK);
+}
+
+#[cfg(not(feature = "xt-keyboard"))]
 pub fn at_idle(p: &msp430g2211::PORT_1_2) {
     set(p, Pins::AT_CLK);
     set(p, Pins::AT_DATA);
     clear_port_reg(&p.p1dir, Pins::AT_MASK);
 }
 
+#[cfg(not(feature = "xt-keyboard"))]
 pub fn at_inhibit(p: &msp430g2211::PORT_1_2) {
     unset(p, Pins::AT_CLK);
     set(p, Pins::AT_DATA);
     set_port_reg(&p.p1dir, Pins::AT_MASK);
 }
 
-pub fn xt_out(p: &msp430g2211::PORT_1_2) {
-    set_port_reg(&p.p1out, Pins::XT_MASK);
-    set_port_reg(&p.p1dir, Pins::XT_MASK);
+// The aux port works like the keyboard's.
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+pub fn aux_init(p: &msp430g2211::PORT_1_2) {
+    clear_port_reg(&p.p1ifg, AUX_CLK);
+    set_port_reg(&p.p1ies, AUX_CLK);
+    set_port_reg(&p.p1ie, AUX_CLK);
+}
+
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+pub fn aux_idle(p: &msp430g2211::PORT_1_2) {
+    set(p, AUX_MASK);
+    clear_port_reg(&p.p1dir, AUX_MASK);
+}
+
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+pub fn aux_inhibit(p: &msp430g2211::PORT_1_2) {
+    unset(p, AUX_CLK);
+    set(p, AUX_DATA);
+    set_port_reg(&p.p1dir, AUX_MASK);
+}
+
+// The serial line rests high.
+#[cfg(feature = "ps2-mouse")]
+pub fn mouse_init(p: &msp430g2211::PORT_1_2) {
+    set(p, Pins::MOUSE_TX);
+    set_port_reg(&p.p1dir, Pins::MOUSE_TX);
+
+    // XIN only works as an input with XOUT taken off the crystal as well.
+    p.p2sel
+        .modify(|r, w| w.p2sel().bits(r.bits() & !(MOUSE_RTS | XOUT)));
+    p.p2dir.modify(|r, w| w.p2dir().bits(r.bits() & !MOUSE_RTS));
+    p.p2ies.modify(|r, w| w.p2ies().bits(r.bits() | MOUSE_RTS));
+    clear_mouse_rts_int(p);
+    p.p2ie.modify(|r, w| w.p2ie().bits(r.bits() | MOUSE_RTS));
+}
+
+#[cfg(feature = "ps2-mouse")]
+pub fn mouse_rts_int_pending(p: &msp430g2211::PORT_1_2) -> bool {
+    p.p2ifg.read().bits() & p.p2ie.read().bits() & MOUSE_RTS != 0
+}
+
+#[cfg(feature = "ps2-mouse")]
+pub fn clear_mouse_rts_int(p: &msp430g2211::PORT_1_2) {
+    p.p2ifg.modify(|r, w| w.p2ifg().bits(r.bits() & !MOUSE_RTS));
+}
+
+#[cfg(not(any(feature = "pcjr", feature = "ascii", feature = "xt-keyboard")))]
+pub fn xt_out(p: &msp430g2211::PORT_1_2, xt: XtPort) {
+    set_port_reg(&p.p1out, xt.clk() | xt.data());
+    set_port_reg(&p.p1dir, xt.clk() | xt.data());
+}
+
+// Lets the pins float high, or pulls them low, like the other side does.
+#[cfg(any(feature = "at-host", feature = "xt-keyboard"))]
+pub fn open_drain(p: &msp430g2211::PORT_1_2, pins: Pins, high: bool) {
+    if high {
+        clear_port_reg(&p.p1dir, pins);
+    } else {
+        unset(p, pins);
+        set_port_reg(&p.p1dir, pins);
+    }
+}
+
+// Drives XT_DATA as a single line resting at idle, like the PCjr link or a
+// serial port. There's no clock for XT_SENSE to watch.
+#[cfg(any(feature = "pcjr", feature = "ascii"))]
+pub fn xt_line_out(p: &msp430g2211::PORT_1_2, idle: bool) {
+    clear_port_reg(&p.p1ie, Pins::XT_SENSE);
+
+    if idle {
+        set(p, Pins::XT_DATA);
+    } else {
+        unset(p, Pins::XT_DATA);
+    }
+
+    set_port_reg(&p.p1dir, Pins::XT_DATA);
 }
 
-pub fn xt_in(p: &msp430g2211::PORT_1_2) {
-    set_port_reg(&p.p1out, Pins::XT_DATA);
-    clear_port_reg(&p.p1dir, Pins::XT_MASK);
+#[cfg(not(any(feature = "pcjr", feature = "ascii", feature = "xt-keyboard")))]
+pub fn xt_in(p: &msp430g2211::PORT_1_2, xt: XtPort) {
+    set_port_reg(&p.p1out, xt.data());
+    clear_port_reg(&p.p1dir, xt.clk() | xt.data());
 }
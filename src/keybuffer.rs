@@ -1,73 +1,161 @@
-pub struct KeycodeBuffer {
-    head: u8,
-    tail: u8,
-    contents: [u16; 16],
+use bit_reverse::BitwiseReverse;
+use bitflags::bitflags;
+use portable_atomic::{AtomicU16, AtomicU8, Ordering};
+
+bitflags! {
+    #[derive(Default, Clone, Copy)]
+    pub struct Status: u8 {
+        const PARITY = 0b0000_0001; // Parity bit didn't match; byte is garbage.
+        const FRAMING = 0b0000_0010; // Bad start or stop bit; byte is garbage.
+        const OVERRUN = 0b0000_0100; // Bytes were lost right after this one.
+    }
+}
+
+// A byte from the keyboard, already stripped of its frame.
+#[derive(Clone, Copy)]
+pub struct Keycode {
+    pub byte: u8,
+    pub status: Status,
+}
+
+impl Keycode {
+    pub fn is_valid(self) -> bool {
+        !self.status.intersects(Status::PARITY | Status::FRAMING)
+    }
 }
 
-impl KeycodeBuffer {
-    pub const fn new() -> KeycodeBuffer {
+// Single-producer, single-consumer queue of keycodes. Only the PORT1 ISR
+// calls put (and advances tail); only the main loop calls take and flush (and
+// advances head). head and tail run freely and wrap at 256, so N must be a
+// power of two. Status is kept as one bit per slot in each of parity,
+// framing and overrun, so N can be at most 16.
+pub struct KeycodeBuffer<const N: usize> {
+    head: AtomicU8,
+    tail: AtomicU8,
+    contents: [AtomicU8; N],
+    parity: AtomicU16,
+    framing: AtomicU16,
+    overrun: AtomicU16,
+}
+
+impl<const N: usize> KeycodeBuffer<N> {
+    const SIZE_OK: () = assert!(N.is_power_of_two() && N <= 16);
+
+    pub const fn new() -> KeycodeBuffer<N> {
+        let () = Self::SIZE_OK;
+
         KeycodeBuffer {
-            head: 0,
-            tail: 0,
-            contents: [0; 16],
+            head: AtomicU8::new(0),
+            tail: AtomicU8::new(0),
+            contents: [const { AtomicU8::new(0) }; N],
+            parity: AtomicU16::new(0),
+            framing: AtomicU16::new(0),
+            overrun: AtomicU16::new(0),
         }
     }
 
-    pub fn flush(&mut self) {
-        self.tail = 0;
-        self.head = 0;
+    // Consumer only.
+    pub fn flush(&self) {
+        self.head
+            .store(self.tail.load(Ordering::SeqCst), Ordering::SeqCst);
     }
 
     pub fn is_empty(&self) -> bool {
-        self.head.wrapping_sub(self.tail) == 0
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> u8 {
+        self.tail
+            .load(Ordering::SeqCst)
+            .wrapping_sub(self.head.load(Ordering::SeqCst))
     }
 
-    pub fn put(&mut self, in_key: u16) -> Result<(), ()> {
-        // if self.tail.wrapping_sub(self.head) >= 16 might be possible!
-        if self.tail.wrapping_sub(self.head) >= 15 {
+    // Producer only. When full, the newest keycode is marked so the consumer
+    // knows where the gap is.
+    pub fn put(&self, in_key: Keycode) -> Result<(), ()> {
+        let tail = self.tail.load(Ordering::SeqCst);
+
+        if usize::from(self.len()) >= N {
+            Self::set_flag(&self.overrun, tail.wrapping_sub(1), true);
             Err(())
         } else {
             /* The most space-efficient way to add/remove queue elements is to
             force the array access to be within bounds by ignoring the top bits
             (equivalent to "% power_of_two"). This will optimize out the bounds
             check. */
-            if let Some(buf_ref) = self.contents.get_mut(usize::from(self.tail % 16)) {
-                *buf_ref = in_key;
-                self.tail = self.tail.wrapping_add(1);
-                Ok(())
-            } else {
-                Err(())
-            }
+            let slot = self.contents.get(usize::from(tail) % N).ok_or(())?;
+            slot.store(in_key.byte, Ordering::SeqCst);
+            Self::set_flag(&self.parity, tail, in_key.status.contains(Status::PARITY));
+            Self::set_flag(&self.framing, tail, in_key.status.contains(Status::FRAMING));
+            Self::set_flag(&self.overrun, tail, in_key.status.contains(Status::OVERRUN));
+            // The slot is written before the consumer can see it.
+            self.tail.store(tail.wrapping_add(1), Ordering::SeqCst);
+            Ok(())
         }
     }
 
-    pub fn take(&mut self) -> Option<u16> {
+    // Consumer only.
+    pub fn take(&self) -> Option<Keycode> {
         if self.is_empty() {
             None
         } else {
             // Same logic applies as with tail.
-            let out_key = self.contents.get(usize::from(self.head % 16));
+            let head = self.head.load(Ordering::SeqCst);
+            let out_key = self.contents.get(usize::from(head) % N).map(|k| {
+                let mut status = Status::empty();
+                status.set(Status::PARITY, Self::flag(&self.parity, head));
+                status.set(Status::FRAMING, Self::flag(&self.framing, head));
+                status.set(Status::OVERRUN, Self::flag(&self.overrun, head));
+
+                Keycode {
+                    byte: k.load(Ordering::SeqCst),
+                    status,
+                }
+            });
 
             if out_key.is_some() {
-                self.head = self.head.wrapping_add(1);
+                // The slot is read before the producer can reuse it.
+                self.head.store(head.wrapping_add(1), Ordering::SeqCst);
             }
 
-            out_key.copied()
+            out_key
         }
     }
+
+    // A single read-modify-write, so the other slots' bits can't be lost to
+    // anything that runs in between.
+    fn set_flag(map: &AtomicU16, pos: u8, on: bool) {
+        let bit = 1 << (usize::from(pos) % N);
+
+        if on {
+            map.fetch_or(bit, Ordering::SeqCst);
+        } else {
+            map.fetch_and(!bit, Ordering::SeqCst);
+        }
+    }
+
+    fn flag(map: &AtomicU16, pos: u8) -> bool {
+        map.load(Ordering::SeqCst) & (1 << (usize::from(pos) % N)) != 0
+    }
 }
 
 #[derive(Clone, Copy)]
 pub struct KeyIn {
     pos: u8,
     contents: u16,
+    last_bit: u16, // TIMER_A2 count at the previous bit.
 }
 
 impl KeyIn {
+    // The slowest PS/2 clock is 10kHz, so 250us (in 10us timer ticks) with no
+    // edge means the rest of the frame isn't coming.
+    const BIT_TIMEOUT: u16 = 25;
+
     pub const fn new() -> KeyIn {
         KeyIn {
             pos: 0,
             contents: 0,
+            last_bit: 0,
         }
     }
 
@@ -75,14 +163,35 @@ impl KeyIn {
         self.pos >= 11
     }
 
+    pub fn is_empty(self) -> bool {
+        self.pos == 0
+    }
+
     pub fn clear(&mut self) {
         self.pos = 0;
         self.contents = 0;
     }
 
-    pub fn shift_in(&mut self, bit: bool) -> Result<(), ()> {
-        // TODO: A nonzero start value (when self.pos == 0) is a runtime invariant violation.
-        if self.is_full() {
+    // Only the stop bit is missing and it isn't overdue.
+    pub fn is_finishing(self, now: u16) -> bool {
+        self.pos >= 10 && now.wrapping_sub(self.last_bit) <= Self::BIT_TIMEOUT
+    }
+
+    // now is the TIMER_A2 count when the clock edge arrived. A partial frame
+    // that went stale (spurious edge, keyboard unplugged mid-frame) is
+    // dropped, and a frame can only start with a 0 start bit, so one bad
+    // edge can't misalign every frame after it.
+    pub fn shift_in(&mut self, bit: bool, now: u16) -> Result<(), ()> {
+        let stale = now.wrapping_sub(self.last_bit) > Self::BIT_TIMEOUT;
+        self.last_bit = now;
+
+        if !self.is_empty() && stale {
+            self.clear();
+        }
+
+        if self.is_empty() && bit {
+            Ok(())
+        } else if self.is_full() {
             Err(())
         } else {
             self.contents = (self.contents << 1) | u16::from(bit);
@@ -96,10 +205,27 @@ impl KeyIn {
         }
     }
 
-    pub fn take(&mut self) -> Option<u16> {
+    // Frames arrive start bit first, then data LSB first, parity (odd), and
+    // stop, so the data lands bit-reversed in bits 9-2.
+    pub fn take(&mut self) -> Option<Keycode> {
         if self.is_full() {
             self.pos = 0;
-            Some(self.contents)
+
+            let [data, _] = (self.contents >> 2).to_le_bytes();
+            let mut status = Status::empty();
+            status.set(
+                Status::FRAMING,
+                self.contents & (1 << 10) != 0 || self.contents & 0x01 == 0,
+            );
+            status.set(
+                Status::PARITY,
+                ((self.contents >> 1) & 0x1ff).count_ones() % 2 == 0,
+            );
+
+            Some(Keycode {
+                byte: data.swap_bits(),
+                status,
+            })
         } else {
             None
         }
@@ -110,14 +236,31 @@ impl KeyIn {
 pub struct KeyOut {
     pos: u8,
     contents: u16,
+    last_edge: u16, // TIMER_A2 count at the previous clock edge.
 }
 
 impl KeyOut {
+    // The fastest PS/2 clock is 16.7kHz (60us); edges closer than 40us, in
+    // 10us timer ticks, are glitches.
+    const MIN_EDGE: u16 = 4;
+
     pub const fn new() -> KeyOut {
         KeyOut {
             pos: 10,
             contents: 0,
+            last_edge: 0,
+        }
+    }
+
+    // Returns false for an edge that came too early to be real.
+    pub fn edge(&mut self, now: u16) -> bool {
+        let real = now.wrapping_sub(self.last_edge) >= Self::MIN_EDGE;
+
+        if real {
+            self.last_edge = now;
         }
+
+        real
     }
 
     pub fn is_empty(self) -> bool {
@@ -142,11 +285,12 @@ impl KeyOut {
         }
     }
 
-    pub fn put(&mut self, byte: u8) -> Result<(), ()> {
+    pub fn put(&mut self, byte: u8, now: u16) -> Result<(), ()> {
         if !self.is_empty() {
             return Err(());
         }
 
+        self.last_edge = now;
         let num_ones = byte.count_ones();
         let stop_bit: u16 = 1 << 9;
         let parity_bit: u16 = if num_ones % 2 == 0 { 1 << 8 } else { 0 };
@@ -155,3 +299,512 @@ impl KeyOut {
         Ok(())
     }
 }
+
+// Bytes waiting to be sent to the XT host.
+pub struct XtBuffer {
+    head: u8,
+    tail: u8,
+    contents: [u8; 8],
+}
+
+impl XtBuffer {
+    pub const CAPACITY: u8 = 8;
+
+    pub const fn new() -> XtBuffer {
+        XtBuffer {
+            head: 0,
+            tail: 0,
+            contents: [0; 8],
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.tail = 0;
+        self.head = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.wrapping_sub(self.tail) == 0
+    }
+
+    pub fn len(&self) -> u8 {
+        self.tail.wrapping_sub(self.head)
+    }
+
+    // All or nothing; the host never sees half of a multi-byte sequence
+    // followed by a stall.
+    pub fn put_all<I: IntoIterator<Item = u8>>(&mut self, bytes: I) -> Result<(), ()> {
+        let mut tail = self.tail;
+
+        for b in bytes {
+            if tail.wrapping_sub(self.head) >= 8 {
+                return Err(());
+            }
+
+            // Slots past self.tail are free, so filling them early is fine.
+            *self.contents.get_mut(usize::from(tail % 8)).ok_or(())? = b;
+            tail = tail.wrapping_add(1);
+        }
+
+        self.tail = tail;
+        Ok(())
+    }
+
+    pub fn take(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            None
+        } else {
+            let out = self.contents.get(usize::from(self.head % 8));
+
+            if out.is_some() {
+                self.head = self.head.wrapping_add(1);
+            }
+
+            out.copied()
+        }
+    }
+}
+
+pub enum XtStep {
+    ClkLow(bool), // Put this bit on DATA, then pull CLK low.
+    ClkHigh,
+}
+
+#[derive(Clone, Copy)]
+pub struct XtOut {
+    pos: u8,
+    end: u8,
+    contents: u16,
+}
+
+impl XtOut {
+    pub const fn new() -> XtOut {
+        XtOut {
+            pos: 20,
+            end: 20,
+            contents: 0,
+        }
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.pos >= self.end // Start bit(s) and Data 0-7, two steps each.
+    }
+
+    pub fn put(&mut self, byte: u8, two_start_bits: bool) -> Result<(), ()> {
+        if !self.is_empty() {
+            return Err(());
+        }
+
+        if two_start_bits {
+            // IBM start bits: a 0, then a 1.
+            self.contents = (u16::from(byte) << 2) | 0b10;
+            self.end = 20;
+        } else {
+            self.contents = (u16::from(byte) << 1) | 0b1;
+            self.end = 18;
+        }
+
+        self.pos = 0;
+        Ok(())
+    }
+
+    pub fn step(&mut self) -> Option<XtStep> {
+        if self.is_empty() {
+            None
+        } else {
+            let step = if self.pos % 2 == 0 {
+                XtStep::ClkLow((self.contents & 0x01) == 1)
+            } else {
+                self.contents >>= 1;
+                XtStep::ClkHigh
+            };
+            self.pos += 1;
+            Some(step)
+        }
+    }
+}
+
+// Receives frames from an XT keyboard, one bit per falling clock edge: IBM's
+// 0 start bit (clones skip it), a 1 start bit, then Data 0-7.
+#[cfg(feature = "xt-keyboard")]
+#[derive(Clone, Copy)]
+pub struct XtIn {
+    pos: u8, // Data bits received, plus one once the start bit is in.
+    contents: u8,
+    last_bit: u16, // TIMER_A2 count at the previous bit.
+}
+
+#[cfg(feature = "xt-keyboard")]
+impl XtIn {
+    // XT keyboards clock at about 10kHz, like the slowest PS/2 ones.
+    const BIT_TIMEOUT: u16 = 25;
+
+    pub const fn new() -> XtIn {
+        XtIn {
+            pos: 0,
+            contents: 0,
+            last_bit: 0,
+        }
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.pos == 0
+    }
+
+    pub fn shift_in(&mut self, bit: bool, now: u16) -> Option<u8> {
+        // The rest of a stale frame isn't coming.
+        if now.wrapping_sub(self.last_bit) > Self::BIT_TIMEOUT {
+            self.pos = 0;
+        }
+
+        self.last_bit = now;
+
+        if self.pos == 0 {
+            if bit {
+                self.pos = 1;
+            }
+
+            return None;
+        }
+
+        self.contents = (self.contents >> 1) | if bit { 0x80 } else { 0 };
+        self.pos += 1;
+
+        if self.pos > 8 {
+            self.pos = 0;
+            Some(self.contents)
+        } else {
+            None
+        }
+    }
+}
+
+// Sends bytes down the PCjr keyboard link. Each bit takes two halves, high
+// then low for a 1 and low then high for a 0: a 1 start bit, Data 0-7 and odd
+// parity, then the line rests low for 11 stop bits.
+#[cfg(feature = "pcjr")]
+#[derive(Clone, Copy)]
+pub struct PcjrOut {
+    pos: u8,
+    contents: u16,
+}
+
+#[cfg(feature = "pcjr")]
+impl PcjrOut {
+    // 440us bits, in 10us timer ticks.
+    const HALF_BIT: u16 = 22;
+    const END: u8 = 2 * (10 + 11);
+
+    pub const fn new() -> PcjrOut {
+        PcjrOut {
+            pos: Self::END,
+            contents: 0,
+        }
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.pos >= Self::END
+    }
+
+    pub fn put(&mut self, byte: u8) -> Result<(), ()> {
+        if !self.is_empty() {
+            return Err(());
+        }
+
+        let parity = u16::from(byte.count_ones() % 2 == 0);
+        self.contents = (parity << 9) | (u16::from(byte) << 1) | 0b1;
+        self.pos = 0;
+        Ok(())
+    }
+
+    // The level to put on the line for the next half bit, and for how long.
+    pub fn step(&mut self) -> Option<(bool, u16)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let level = if self.pos < 20 {
+            let bit = (self.contents >> (self.pos / 2)) & 0x01 == 1;
+            bit == (self.pos % 2 == 0)
+        } else {
+            false
+        };
+
+        self.pos += 1;
+        Some((level, Self::HALF_BIT))
+    }
+}
+
+// Sends bytes as serial with no parity and one stop bit, LSB first. Edges
+// are placed from the start of the frame, so rounding to timer ticks doesn't
+// add up. There's no hardware divide, so new() works the bit times out and
+// belongs in a const.
+#[cfg(any(feature = "ascii", feature = "ps2-mouse"))]
+#[derive(Clone, Copy)]
+pub struct UartOut {
+    pos: u8,
+    end: u8,
+    contents: u16,
+    bit_ticks: u16, // Timer ticks in a bit, rounded down.
+    long_bits: u16, // Bits that take a tick more to make up the rounding.
+}
+
+#[cfg(any(feature = "ascii", feature = "ps2-mouse"))]
+impl UartOut {
+    const TIMER_HZ: u32 = 100_000;
+
+    pub const fn new(baud: u32, data_bits: u8) -> UartOut {
+        let end = data_bits + 2; // Start bit, data, Stop bit.
+        let bit_ticks = Self::TIMER_HZ / baud;
+        assert!(end <= 16 && bit_ticks <= 0xffff);
+
+        let mut long_bits = 0;
+        let mut pos = 0;
+        let mut edge = 0;
+
+        while pos < end {
+            let next = (edge + 1) * Self::TIMER_HZ / baud;
+
+            if next - edge * Self::TIMER_HZ / baud > bit_ticks {
+                long_bits |= 1 << pos;
+            }
+
+            pos += 1;
+            edge += 1;
+        }
+
+        let [lo, hi, _, _] = bit_ticks.to_le_bytes();
+
+        UartOut {
+            pos: end,
+            end,
+            contents: 0,
+            bit_ticks: u16::from_le_bytes([lo, hi]),
+            long_bits,
+        }
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.pos >= self.end
+    }
+
+    pub fn put(&mut self, byte: u8) -> Result<(), ()> {
+        if !self.is_empty() {
+            return Err(());
+        }
+
+        // Data bits that don't fit the frame are dropped.
+        let stop = 1 << (self.end - 1);
+        self.contents = ((u16::from(byte) << 1) & (stop - 1)) | stop;
+        self.pos = 0;
+        Ok(())
+    }
+
+    // The level to put on the line for the next bit, and for how long.
+    pub fn step(&mut self) -> Option<(bool, u16)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let level = (self.contents >> self.pos) & 0x01 == 1;
+        let ticks = self.bit_ticks + ((self.long_bits >> self.pos) & 0x01);
+
+        self.pos += 1;
+        Some((level, ticks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> Keycode {
+        Keycode {
+            byte,
+            status: Status::empty(),
+        }
+    }
+
+    #[test]
+    fn keycodes_come_out_in_order_past_the_index_wrap() {
+        let buf = KeycodeBuffer::<4>::new();
+
+        // head and tail wrap at 256, well after the slots do.
+        for i in 0..=600u16 {
+            let [byte, _] = i.to_le_bytes();
+
+            assert!(buf.put(key(byte)).is_ok());
+            assert_eq!(buf.len(), 1);
+            assert_eq!(buf.take().map(|k| k.byte), Some(byte));
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn a_full_buffer_marks_the_newest_keycode() {
+        let buf = KeycodeBuffer::<4>::new();
+
+        for byte in 1..=4 {
+            assert!(buf.put(key(byte)).is_ok());
+        }
+
+        assert!(buf.put(key(5)).is_err());
+        assert_eq!(buf.len(), 4);
+
+        let overruns: Vec<_> = core::iter::from_fn(|| buf.take())
+            .map(|k| (k.byte, k.status.contains(Status::OVERRUN)))
+            .collect();
+        assert_eq!(overruns, [(1, false), (2, false), (3, false), (4, true)]);
+    }
+
+    #[test]
+    fn an_overrun_after_a_take_marks_the_keycode_still_waiting() {
+        let buf = KeycodeBuffer::<4>::new();
+
+        for byte in 1..=4 {
+            assert!(buf.put(key(byte)).is_ok());
+        }
+
+        assert_eq!(buf.take().map(|k| k.byte), Some(1));
+        assert!(buf.put(key(5)).is_ok());
+        assert!(buf.put(key(6)).is_err());
+
+        let overruns: Vec<_> = core::iter::from_fn(|| buf.take())
+            .map(|k| (k.byte, k.status.contains(Status::OVERRUN)))
+            .collect();
+        assert_eq!(overruns, [(2, false), (3, false), (4, false), (5, true)]);
+    }
+
+    #[test]
+    fn status_belongs_to_its_own_slot() {
+        let buf = KeycodeBuffer::<4>::new();
+        let bad = Keycode {
+            byte: 0x1c,
+            status: Status::PARITY | Status::FRAMING,
+        };
+
+        // Reusing a slot clears what the last keycode left there.
+        for _ in 0..2 {
+            assert!(buf.put(bad).is_ok());
+            assert!(buf.put(key(0x32)).is_ok());
+
+            let first = buf.take().unwrap();
+            assert!(!first.is_valid());
+            assert!(first.status.contains(Status::PARITY | Status::FRAMING));
+            assert!(buf.take().unwrap().status.is_empty());
+        }
+    }
+
+    #[test]
+    fn flush_drops_everything_waiting() {
+        let buf = KeycodeBuffer::<4>::new();
+
+        assert!(buf.put(key(0x1c)).is_ok());
+        assert!(buf.put(key(0x32)).is_ok());
+        buf.flush();
+
+        assert!(buf.is_empty());
+        assert!(buf.take().is_none());
+        assert!(buf.put(key(0x21)).is_ok());
+        assert_eq!(buf.take().map(|k| k.byte), Some(0x21));
+    }
+
+    // Start bit, data LSB first, odd parity, stop bit.
+    fn frame(byte: u8, parity_ok: bool) -> Vec<bool> {
+        let parity = (byte.count_ones() % 2 == 0) == parity_ok;
+        let mut bits = vec![false];
+
+        bits.extend((0..8).map(|i| byte & (1 << i) != 0));
+        bits.extend([parity, true]);
+        bits
+    }
+
+    fn receive(bits: &[bool]) -> Option<Keycode> {
+        let mut key_in = KeyIn::new();
+
+        for (now, &bit) in (10..).step_by(8).zip(bits) {
+            // Err means the frame is complete.
+            if key_in.shift_in(bit, now).is_err() {
+                break;
+            }
+        }
+
+        key_in.take()
+    }
+
+    #[test]
+    fn frames_are_decoded() {
+        let k = receive(&frame(0x1c, true)).unwrap();
+        assert_eq!(k.byte, 0x1c);
+        assert!(k.is_valid());
+
+        let k = receive(&frame(0xf0, false)).unwrap();
+        assert_eq!(k.byte, 0xf0);
+        assert!(k.status.contains(Status::PARITY));
+    }
+
+    #[test]
+    fn a_stale_partial_frame_is_dropped() {
+        let mut key_in = KeyIn::new();
+
+        for &bit in frame(0x55, true).get(..4).unwrap() {
+            assert!(key_in.shift_in(bit, 0).is_ok());
+        }
+
+        for (now, &bit) in (100..).step_by(8).zip(&frame(0x1c, true)) {
+            // Err means the frame is complete.
+            if key_in.shift_in(bit, now).is_err() {
+                break;
+            }
+        }
+
+        assert_eq!(key_in.take().map(|k| k.byte), Some(0x1c));
+    }
+
+    #[test]
+    fn xt_bytes_go_in_all_at_once_or_not_at_all() {
+        let mut buf = XtBuffer::new();
+
+        assert!(buf.put_all([1, 2, 3, 4, 5, 6]).is_ok());
+        assert!(buf.put_all([7, 8, 9]).is_err());
+        assert_eq!(buf.len(), 6);
+        assert!(buf.put_all([7, 8]).is_ok());
+
+        let sent: Vec<_> = core::iter::from_fn(|| buf.take()).collect();
+        assert_eq!(sent, [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[cfg(any(feature = "ascii", feature = "ps2-mouse"))]
+    #[test]
+    fn uart_bit_times_add_up_to_the_baud_rate() {
+        const OUT: UartOut = UartOut::new(9600, 8);
+        let mut out = OUT;
+
+        assert!(out.put(0x4b).is_ok());
+        let bits: Vec<_> = core::iter::from_fn(|| out.step()).collect();
+        let levels: Vec<_> = bits.iter().map(|&(level, _)| u8::from(level)).collect();
+        assert_eq!(levels, [0, 1, 1, 0, 1, 0, 0, 1, 0, 1]);
+
+        // 10.42 ticks a bit.
+        let edges: Vec<_> = bits
+            .iter()
+            .scan(0, |at, &(_, ticks)| {
+                *at += ticks;
+                Some(*at)
+            })
+            .collect();
+        assert_eq!(edges, [10, 20, 31, 41, 52, 62, 72, 83, 93, 104]);
+    }
+
+    #[cfg(any(feature = "ascii", feature = "ps2-mouse"))]
+    #[test]
+    fn uart_data_bits_past_the_frame_are_dropped() {
+        let mut out = UartOut::new(1200, 7);
+
+        assert!(out.put(0xff).is_ok());
+        let bits: Vec<_> = core::iter::from_fn(|| out.step()).collect();
+        assert_eq!(bits.len(), 9);
+        assert_eq!(bits.iter().map(|&(_, ticks)| ticks).sum::<u16>(), 750);
+        assert!(bits.iter().skip(1).all(|&(level, _)| level));
+    }
+}
@@ -0,0 +1,558 @@
+use crate::keystate::KeyEvent;
+#[cfg(feature = "layouts")]
+use crate::keystate::LedMask;
+#[cfg(feature = "xt-keyboard")]
+use core::convert::TryFrom;
+
+static KEYCODE_LUT: [u8; 133] =
+    // 0    1    2    3    4    5    6    7    8    9    A    B    C    D    E    F
+    [
+        0x00, 0x43, 0x00, 0x3F, 0x3D, 0x3B, 0x3C, 0x58, 0x00, 0x44, 0x42, 0x40, 0x3E, 0x0F, 0x29,
+        0x00, 0x00, 0x38, 0x2A, 0x00, 0x1D, 0x10, 0x02, 0x00, 0x00, 0x00, 0x2C, 0x1F, 0x1E, 0x11,
+        0x03, 0x00, 0x00, 0x2E, 0x2D, 0x20, 0x12, 0x05, 0x04, 0x00, 0x00, 0x39, 0x2F, 0x21, 0x14,
+        0x13, 0x06, 0x00, 0x00, 0x31, 0x30, 0x23, 0x22, 0x15, 0x07, 0x00, 0x00, 0x00, 0x32, 0x24,
+        0x16, 0x08, 0x09, 0x00, 0x00, 0x33, 0x25, 0x17, 0x18, 0x0B, 0x0A, 0x00, 0x00, 0x34, 0x35,
+        0x26, 0x27, 0x19, 0x0C, 0x00, 0x00, 0x00, 0x28, 0x00, 0x1A, 0x0D, 0x00, 0x00, 0x3A, 0x36,
+        0x1C, 0x1B, 0x00, 0x2B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0E, 0x00, 0x00,
+        0x4F, 0x00, 0x4B, 0x47, 0x00, 0x00, 0x00, 0x52, 0x53, 0x50, 0x4C, 0x4D, 0x48, 0x01, 0x45,
+        0x57, 0x4E, 0x51, 0x4A, 0x37, 0x49, 0x46, 0x00, 0x00, 0x00, 0x00, 0x41, 0x54,
+    ];
+
+// Keys without an XT equivalent map to 0x00.
+pub fn to_xt(at_in: u8) -> Option<u8> {
+    KEYCODE_LUT
+        .get(usize::from(at_in))
+        .copied()
+        .filter(|x| *x != 0)
+}
+
+// The Set 2 code for an XT keyboard's key.
+#[cfg(feature = "xt-keyboard")]
+pub fn from_xt(xt_in: u8) -> Option<u8> {
+    KEYCODE_LUT
+        .iter()
+        .position(|x| *x == xt_in && xt_in != 0)
+        .and_then(|i| u8::try_from(i).ok())
+}
+
+// Keys whose Tandy 1000 code differs from the IBM XT one, as (Set 2 code,
+// extended, Tandy code). The arrows took over the codes of `, \ and keypad
+// -/+, which the Tandy keyboard reports no other way; a 0 drops the key.
+#[cfg(feature = "tandy1000")]
+static TANDY: [(u8, bool, u8); 13] = [
+    (0x75, true, 0x29),  // Up
+    (0x6b, true, 0x2b),  // Left
+    (0x72, true, 0x4a),  // Down
+    (0x74, true, 0x4e),  // Right
+    (0x6c, true, 0x58),  // Home
+    (0x5a, true, 0x57),  // Keypad Enter
+    (0x78, false, 0x59), // F11
+    (0x07, false, 0x5a), // F12
+    (0x7e, false, 0x46), // Scroll Lock -> Hold
+    (0x0e, false, 0x00), // `
+    (0x5d, false, 0x00), // \
+    (0x7b, false, 0x00), // Keypad -
+    (0x79, false, 0x00), // Keypad +
+];
+
+// Tandy keyboards predate the E0 prefix, so extended keys not in TANDY send
+// the code of the IBM key they duplicate.
+#[cfg(feature = "tandy1000")]
+pub fn to_tandy(at_in: u8, extended: bool) -> Option<u8> {
+    TANDY
+        .iter()
+        .find(|(k, e, _)| *k == at_in && *e == extended)
+        .map_or(to_xt(at_in), |(_, _, t)| Some(*t).filter(|t| *t != 0))
+}
+
+// Keys the PCjr keyboard lacks or has elsewhere, as (Set 2 code, extended,
+// PCjr code, with Fn). The PCjr BIOS turns Fn combinations back into the
+// 83-key codes. The keypad becomes the top row; a 0 drops the key.
+#[cfg(feature = "pcjr")]
+static PCJR: [(u8, bool, u8, bool); 33] = [
+    (0x05, false, 0x02, true),  // F1 -> Fn+1
+    (0x06, false, 0x03, true),  // F2 -> Fn+2
+    (0x04, false, 0x04, true),  // F3 -> Fn+3
+    (0x0c, false, 0x05, true),  // F4 -> Fn+4
+    (0x03, false, 0x06, true),  // F5 -> Fn+5
+    (0x0b, false, 0x07, true),  // F6 -> Fn+6
+    (0x83, false, 0x08, true),  // F7 -> Fn+7
+    (0x0a, false, 0x09, true),  // F8 -> Fn+8
+    (0x01, false, 0x0a, true),  // F9 -> Fn+9
+    (0x09, false, 0x0b, true),  // F10 -> Fn+0
+    (0x78, false, 0x00, false), // F11
+    (0x07, false, 0x00, false), // F12
+    (0x6c, true, 0x4b, true),   // Home -> Fn+Left
+    (0x69, true, 0x4d, true),   // End -> Fn+Right
+    (0x7d, true, 0x48, true),   // Page Up -> Fn+Up
+    (0x7a, true, 0x50, true),   // Page Down -> Fn+Down
+    (0x7c, true, 0x19, true),   // Print Screen -> Fn+P
+    (0x7e, true, 0x30, true),   // Ctrl+Break -> Fn+B
+    (0x7e, false, 0x1f, true),  // Scroll Lock -> Fn+S
+    (0x77, false, 0x31, true),  // Num Lock -> Fn+N
+    (0x7b, false, 0x0c, true),  // Keypad - -> Fn+-
+    (0x79, false, 0x0d, true),  // Keypad + -> Fn+=
+    (0x70, false, 0x0b, false), // Keypad 0 -> 0
+    (0x69, false, 0x02, false), // Keypad 1 -> 1
+    (0x72, false, 0x03, false), // Keypad 2 -> 2
+    (0x7a, false, 0x04, false), // Keypad 3 -> 3
+    (0x6b, false, 0x05, false), // Keypad 4 -> 4
+    (0x73, false, 0x06, false), // Keypad 5 -> 5
+    (0x74, false, 0x07, false), // Keypad 6 -> 6
+    (0x6c, false, 0x08, false), // Keypad 7 -> 7
+    (0x75, false, 0x09, false), // Keypad 8 -> 8
+    (0x7d, false, 0x0a, false), // Keypad 9 -> 9
+    (0x71, false, 0x34, false), // Keypad . -> .
+];
+
+// The PCjr code for a key, and whether Fn goes with it. Extended keys not in
+// PCJR send the code of the IBM key they duplicate, without E0.
+#[cfg(feature = "pcjr")]
+pub fn to_pcjr(at_in: u8, extended: bool) -> Option<(u8, bool)> {
+    PCJR.iter()
+        .find(|(k, e, _, _)| *k == at_in && *e == extended)
+        .map_or(to_xt(at_in).map(|x| (x, false)), |(_, _, p, f)| {
+            Some((*p, *f)).filter(|(p, _)| *p != 0)
+        })
+}
+
+// Characters for Set 2 codes, as (Set 2 code, plain, with Shift).
+#[cfg(feature = "ascii")]
+static ASCII: [(u8, u8, u8); 66] = [
+    (0x0e, b'`', b'~'),
+    (0x16, b'1', b'!'),
+    (0x1e, b'2', b'@'),
+    (0x26, b'3', b'#'),
+    (0x25, b'4', b'$'),
+    (0x2e, b'5', b'%'),
+    (0x36, b'6', b'^'),
+    (0x3d, b'7', b'&'),
+    (0x3e, b'8', b'*'),
+    (0x46, b'9', b'('),
+    (0x45, b'0', b')'),
+    (0x4e, b'-', b'_'),
+    (0x55, b'=', b'+'),
+    (0x5d, b'\\', b'|'),
+    (0x66, 0x08, 0x08), // Backspace
+    (0x0d, b'\t', b'\t'),
+    (0x15, b'q', b'Q'),
+    (0x1d, b'w', b'W'),
+    (0x24, b'e', b'E'),
+    (0x2d, b'r', b'R'),
+    (0x2c, b't', b'T'),
+    (0x35, b'y', b'Y'),
+    (0x3c, b'u', b'U'),
+    (0x43, b'i', b'I'),
+    (0x44, b'o', b'O'),
+    (0x4d, b'p', b'P'),
+    (0x54, b'[', b'{'),
+    (0x5b, b']', b'}'),
+    (0x1c, b'a', b'A'),
+    (0x1b, b's', b'S'),
+    (0x23, b'd', b'D'),
+    (0x2b, b'f', b'F'),
+    (0x34, b'g', b'G'),
+    (0x33, b'h', b'H'),
+    (0x3b, b'j', b'J'),
+    (0x42, b'k', b'K'),
+    (0x4b, b'l', b'L'),
+    (0x4c, b';', b':'),
+    (0x52, b'\'', b'"'),
+    (0x5a, b'\r', b'\r'), // Enter
+    (0x1a, b'z', b'Z'),
+    (0x22, b'x', b'X'),
+    (0x21, b'c', b'C'),
+    (0x2a, b'v', b'V'),
+    (0x32, b'b', b'B'),
+    (0x31, b'n', b'N'),
+    (0x3a, b'm', b'M'),
+    (0x41, b',', b'<'),
+    (0x49, b'.', b'>'),
+    (0x4a, b'/', b'?'),
+    (0x29, b' ', b' '),
+    (0x76, 0x1b, 0x1b), // Esc
+    (0x70, b'0', b'0'), // Keypad from here on.
+    (0x69, b'1', b'1'),
+    (0x72, b'2', b'2'),
+    (0x7a, b'3', b'3'),
+    (0x6b, b'4', b'4'),
+    (0x73, b'5', b'5'),
+    (0x74, b'6', b'6'),
+    (0x6c, b'7', b'7'),
+    (0x75, b'8', b'8'),
+    (0x7d, b'9', b'9'),
+    (0x71, b'.', b'.'),
+    (0x7c, b'*', b'*'),
+    (0x7b, b'-', b'-'),
+    (0x79, b'+', b'+'),
+];
+
+#[cfg(feature = "ascii")]
+pub fn to_ascii(at_in: u8) -> Option<(u8, u8)> {
+    ASCII
+        .iter()
+        .find(|(k, _, _)| *k == at_in)
+        .map(|(_, p, s)| (*p, *s))
+}
+
+// What an ANSI/VT100 terminal sends for keys without a character, as (Set 2
+// code, extended, bytes). F1-F4 are the VT100's PF1-PF4; the rest follow the
+// VT220.
+#[cfg(feature = "ascii")]
+static ANSI: [(u8, bool, &[u8]); 24] = [
+    (0x75, true, b"\x1b[A"),    // Up
+    (0x72, true, b"\x1b[B"),    // Down
+    (0x74, true, b"\x1b[C"),    // Right
+    (0x6b, true, b"\x1b[D"),    // Left
+    (0x6c, true, b"\x1b[H"),    // Home
+    (0x69, true, b"\x1b[F"),    // End
+    (0x70, true, b"\x1b[2~"),   // Insert
+    (0x71, true, b"\x1b[3~"),   // Delete
+    (0x7d, true, b"\x1b[5~"),   // Page Up
+    (0x7a, true, b"\x1b[6~"),   // Page Down
+    (0x4a, true, b"/"),         // Keypad /
+    (0x5a, true, b"\r"),        // Keypad Enter
+    (0x05, false, b"\x1bOP"),   // F1
+    (0x06, false, b"\x1bOQ"),   // F2
+    (0x04, false, b"\x1bOR"),   // F3
+    (0x0c, false, b"\x1bOS"),   // F4
+    (0x03, false, b"\x1b[15~"), // F5
+    (0x0b, false, b"\x1b[17~"), // F6
+    (0x83, false, b"\x1b[18~"), // F7
+    (0x0a, false, b"\x1b[19~"), // F8
+    (0x01, false, b"\x1b[20~"), // F9
+    (0x09, false, b"\x1b[21~"), // F10
+    (0x78, false, b"\x1b[23~"), // F11
+    (0x07, false, b"\x1b[24~"), // F12
+];
+
+#[cfg(feature = "ascii")]
+pub fn to_ansi(at_in: u8, extended: bool) -> Option<&'static [u8]> {
+    ANSI.iter()
+        .find(|(k, e, _)| *k == at_in && *e == extended)
+        .map(|(_, _, b)| *b)
+}
+
+// XT make codes; Ctrl, Alt, Del.
+#[cfg(feature = "power-ctrl-alt-del")]
+static CTRL_ALT_DEL: [u8; 3] = [0x1d, 0x38, 0x53];
+
+// Power is right next to keys people actually mean to press, so rebooting
+// the host with it has to be asked for.
+#[cfg(all(feature = "special-keys", not(feature = "power-ctrl-alt-del")))]
+const POWER: Action = Action::Drop;
+#[cfg(feature = "power-ctrl-alt-del")]
+const POWER: Action = Action::Chord(&CTRL_ALT_DEL);
+
+// Right Windows switches hosts when there are two.
+#[cfg(all(feature = "special-keys", not(feature = "kvm")))]
+const RIGHT_WINDOWS: Action = Action::Drop;
+#[cfg(feature = "kvm")]
+const RIGHT_WINDOWS: Action = Action::Function(Function::SwitchHost);
+
+// Menu cycles through the layouts, if there are any.
+#[cfg(all(feature = "special-keys", not(feature = "layouts")))]
+const MENU: Action = Action::Drop;
+#[cfg(feature = "layouts")]
+const MENU: Action = Action::Function(Function::NextLayout);
+
+// E0-prefixed keys that have no XT equivalent. Change the right-hand side
+// to choose what each key does.
+#[cfg(feature = "special-keys")]
+static SPECIAL_KEYS: [(u8, Action); 6] = [
+    (0x1f, Action::Drop),                              // Left Windows
+    (0x27, RIGHT_WINDOWS),                             // Right Windows
+    (0x2f, MENU),                                      // Menu
+    (0x37, POWER),                                     // Power
+    (0x3f, Action::Function(Function::ResetKeyboard)), // Sleep
+    (0x5e, Action::Drop),                              // Wake
+];
+
+#[cfg(feature = "special-keys")]
+pub fn special(at_in: u8) -> Option<Action> {
+    SPECIAL_KEYS
+        .iter()
+        .find(|(k, _)| *k == at_in)
+        .map(|(_, a)| *a)
+}
+
+// Alternate layouts move keys around at the Set 2 level, so the host
+// (which assumes US QWERTY) sees the key that normally types the wanted
+// character. Pairs are (physical key, key to report instead).
+#[cfg(feature = "layouts")]
+static DVORAK: [(u8, u8); 33] = [
+    (0x4e, 0x54), // - -> [
+    (0x55, 0x5b), // = -> ]
+    (0x15, 0x52), // Q -> '
+    (0x1d, 0x41), // W -> ,
+    (0x24, 0x49), // E -> .
+    (0x2d, 0x4d), // R -> P
+    (0x2c, 0x35), // T -> Y
+    (0x35, 0x2b), // Y -> F
+    (0x3c, 0x34), // U -> G
+    (0x43, 0x21), // I -> C
+    (0x44, 0x2d), // O -> R
+    (0x4d, 0x4b), // P -> L
+    (0x54, 0x4a), // [ -> /
+    (0x5b, 0x55), // ] -> =
+    (0x1b, 0x44), // S -> O
+    (0x23, 0x24), // D -> E
+    (0x2b, 0x3c), // F -> U
+    (0x34, 0x43), // G -> I
+    (0x33, 0x23), // H -> D
+    (0x3b, 0x33), // J -> H
+    (0x42, 0x2c), // K -> T
+    (0x4b, 0x31), // L -> N
+    (0x4c, 0x1b), // ; -> S
+    (0x52, 0x4e), // ' -> -
+    (0x1a, 0x4c), // Z -> ;
+    (0x22, 0x15), // X -> Q
+    (0x21, 0x3b), // C -> J
+    (0x2a, 0x42), // V -> K
+    (0x32, 0x22), // B -> X
+    (0x31, 0x32), // N -> B
+    (0x41, 0x1d), // , -> W
+    (0x49, 0x2a), // . -> V
+    (0x4a, 0x1a), // / -> Z
+];
+
+#[cfg(feature = "layouts")]
+static COLEMAK: [(u8, u8); 17] = [
+    (0x24, 0x2b), // E -> F
+    (0x2d, 0x4d), // R -> P
+    (0x2c, 0x34), // T -> G
+    (0x35, 0x3b), // Y -> J
+    (0x3c, 0x4b), // U -> L
+    (0x43, 0x3c), // I -> U
+    (0x44, 0x35), // O -> Y
+    (0x4d, 0x4c), // P -> ;
+    (0x1b, 0x2d), // S -> R
+    (0x23, 0x1b), // D -> S
+    (0x2b, 0x2c), // F -> T
+    (0x34, 0x23), // G -> D
+    (0x3b, 0x31), // J -> N
+    (0x42, 0x24), // K -> E
+    (0x4b, 0x43), // L -> I
+    (0x4c, 0x44), // ; -> O
+    (0x31, 0x42), // N -> K
+];
+
+// Only the letters and punctuation that AZERTY types unshifted; the
+// number row needs Shift on a US host and is left alone.
+#[cfg(feature = "layouts")]
+static AZERTY: [(u8, u8); 7] = [
+    (0x15, 0x1c), // Q -> A
+    (0x1c, 0x15), // A -> Q
+    (0x1d, 0x1a), // W -> Z
+    (0x1a, 0x1d), // Z -> W
+    (0x4c, 0x3a), // ; -> M
+    (0x3a, 0x41), // M -> ,
+    (0x41, 0x4c), // , -> ;
+];
+
+#[cfg(feature = "layouts")]
+#[derive(Clone, Copy, Default)]
+pub enum Layout {
+    #[default]
+    Qwerty,
+    Dvorak,
+    Colemak,
+    Azerty,
+}
+
+#[cfg(feature = "layouts")]
+impl Layout {
+    pub fn next(self) -> Layout {
+        match self {
+            Layout::Qwerty => Layout::Dvorak,
+            Layout::Dvorak => Layout::Colemak,
+            Layout::Colemak => Layout::Azerty,
+            Layout::Azerty => Layout::Qwerty,
+        }
+    }
+
+    // Shown while the layout key is held.
+    pub fn leds(self) -> LedMask {
+        match self {
+            Layout::Qwerty => LedMask::empty(),
+            Layout::Dvorak => LedMask::SCROLL,
+            Layout::Colemak => LedMask::NUM,
+            Layout::Azerty => LedMask::CAPS,
+        }
+    }
+
+    pub fn remap(self, at_in: u8) -> u8 {
+        let table: &[(u8, u8)] = match self {
+            Layout::Qwerty => &[],
+            Layout::Dvorak => &DVORAK,
+            Layout::Colemak => &COLEMAK,
+            Layout::Azerty => &AZERTY,
+        };
+
+        table
+            .iter()
+            .find(|(k, _)| *k == at_in)
+            .map_or(at_in, |(_, r)| *r)
+    }
+}
+
+// Which keyboard a key came from.
+#[derive(Clone, Copy)]
+pub enum Keyboard {
+    First,
+    #[cfg(feature = "second-keyboard")]
+    Second,
+}
+
+// Keys moved on one keyboard only, before anything else sees them, as (Set 2
+// code, extended, Set 2 code to report, extended). Add an entry, and bump the
+// array length to match, to move another key; a key not listed is left alone.
+static FIRST_REMAP: [(u8, bool, u8, bool); 0] = [];
+// A keypad next to the main keyboard is more use as a macro pad: its
+// operator keys become F9-F12, for programs to bind.
+#[cfg(feature = "second-keyboard")]
+static SECOND_REMAP: [(u8, bool, u8, bool); 4] = [
+    (0x4a, true, 0x01, false),  // Keypad / is F9.
+    (0x7c, false, 0x09, false), // Keypad * is F10.
+    (0x7b, false, 0x78, false), // Keypad - is F11.
+    (0x79, false, 0x07, false), // Keypad + is F12.
+];
+
+pub fn remap_keyboard(kbd: Keyboard, ev: KeyEvent) -> KeyEvent {
+    let table: &[(u8, bool, u8, bool)] = match kbd {
+        Keyboard::First => &FIRST_REMAP,
+        #[cfg(feature = "second-keyboard")]
+        Keyboard::Second => &SECOND_REMAP,
+    };
+
+    table
+        .iter()
+        .find(|(k, e, _, _)| *k == ev.key && *e == ev.extended)
+        .map_or(ev, |(_, _, key, extended)| KeyEvent {
+            key: *key,
+            extended: *extended,
+            ..ev
+        })
+}
+
+// What to do with a key that has no XT equivalent.
+#[cfg(feature = "special-keys")]
+#[derive(Clone, Copy)]
+pub enum Action {
+    Drop,
+    #[allow(dead_code)]
+    Key(u8), // Send this XT make code on press, break code on release.
+    Chord(&'static [u8]), // Send these make codes, then the breaks in reverse, on press.
+    Function(Function),   // Run a converter function on press.
+}
+
+#[cfg(feature = "special-keys")]
+#[derive(Clone, Copy)]
+pub enum Function {
+    ResetKeyboard,
+    #[cfg(feature = "layouts")]
+    NextLayout, // Also shows the new layout on the LEDs until released.
+    #[cfg(feature = "kvm")]
+    SwitchHost, // Types on the other XT host from now on.
+}
+
+#[cfg(all(test, feature = "special-keys"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn special_keys_are_found() {
+        assert!(matches!(
+            special(0x3f),
+            Some(Action::Function(Function::ResetKeyboard))
+        ));
+        assert!(matches!(special(0x1f), Some(Action::Drop)));
+    }
+
+    #[cfg(feature = "layouts")]
+    #[test]
+    fn menu_switches_layouts() {
+        assert!(matches!(
+            special(0x2f),
+            Some(Action::Function(Function::NextLayout))
+        ));
+    }
+
+    #[cfg(not(feature = "power-ctrl-alt-del"))]
+    #[test]
+    fn power_is_dropped_by_default() {
+        assert!(matches!(special(0x37), Some(Action::Drop)));
+    }
+
+    #[cfg(feature = "power-ctrl-alt-del")]
+    #[test]
+    fn power_can_reboot_the_host() {
+        assert!(matches!(
+            special(0x37),
+            Some(Action::Chord(&[0x1d, 0x38, 0x53]))
+        ));
+    }
+
+    #[test]
+    fn other_extended_keys_are_not_special() {
+        // Up, Right Ctrl and keypad Enter all have XT codes of their own.
+        assert!(special(0x75).is_none());
+        assert!(special(0x14).is_none());
+        assert!(special(0x5a).is_none());
+    }
+
+    // Every key a layout moves must still be reachable, and no two keys may
+    // end up typing the same thing.
+    #[cfg(feature = "layouts")]
+    fn assert_permutation(table: &[(u8, u8)]) {
+        for (i, (from, to)) in table.iter().enumerate() {
+            let before = || table.iter().take(i);
+
+            assert!(table.iter().any(|(f, _)| f == to), "{:#04x} is lost", to);
+            assert!(!before().any(|(f, _)| f == from), "{:#04x} twice", from);
+            assert!(!before().any(|(_, t)| t == to), "{:#04x} twice", to);
+        }
+    }
+
+    #[cfg(feature = "layouts")]
+    #[test]
+    fn layouts_only_move_keys_around() {
+        assert_permutation(&DVORAK);
+        assert_permutation(&COLEMAK);
+        assert_permutation(&AZERTY);
+    }
+
+    #[cfg(feature = "layouts")]
+    #[test]
+    fn layouts_cycle_back_to_qwerty() {
+        let mut layout = Layout::Qwerty;
+        let mut leds = Vec::new();
+
+        for _ in 0..4 {
+            layout = layout.next();
+            leds.push(layout.leds().bits());
+        }
+
+        assert!(matches!(layout, Layout::Qwerty));
+        assert_eq!(leds, [0b001, 0b010, 0b100, 0b000]);
+    }
+
+    #[cfg(feature = "layouts")]
+    #[test]
+    fn qwerty_leaves_keys_alone() {
+        for key in 0..=0x84 {
+            assert_eq!(Layout::Qwerty.remap(key), key);
+        }
+    }
+
+    #[cfg(feature = "layouts")]
+    #[test]
+    fn layouts_remap_letters() {
+        assert_eq!(Layout::Dvorak.remap(0x15), 0x52); // Q types '
+        assert_eq!(Layout::Colemak.remap(0x1b), 0x2d); // S types R
+        assert_eq!(Layout::Azerty.remap(0x15), 0x1c); // Q types A
+
+        // Keys a layout doesn't move stay put.
+        assert_eq!(Layout::Colemak.remap(0x1c), 0x1c);
+    }
+}
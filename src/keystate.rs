@@ -0,0 +1,265 @@
+use bitflags::bitflags;
+
+#[cfg(feature = "layouts")]
+use crate::keymap::Layout;
+
+// A key going up or down. Keys are identified by their Set 2 code; Pause,
+// which has no code of its own, is reported as an extended Num Lock.
+#[derive(Clone, Copy)]
+pub struct KeyEvent {
+    pub key: u8,
+    pub pressed: bool,
+    pub extended: bool,
+}
+
+impl KeyEvent {
+    pub const PAUSE: u8 = 0x77;
+
+    pub fn is_pause(self) -> bool {
+        self.extended && self.key == Self::PAUSE
+    }
+}
+
+bitflags! {
+    #[derive(Default, Clone, Copy)]
+    pub struct LedMask: u8 {
+        const SCROLL = 0b0000_0001;
+        const NUM = 0b0000_0010;
+        const CAPS = 0b0000_0100;
+    }
+}
+
+// Only the ASCII terminal builds characters out of the modifiers, so the
+// other modes don't track them.
+#[cfg(feature = "ascii")]
+bitflags! {
+    #[derive(Default, Clone, Copy)]
+    pub struct Modifiers: u8 {
+        const LSHIFT = 0b0000_0001;
+        const RSHIFT = 0b0000_0010;
+        const LCTRL = 0b0000_0100;
+        const RCTRL = 0b0000_1000;
+        const LALT = 0b0001_0000;
+        const RALT = 0b0010_0000;
+        const LWIN = 0b0100_0000;
+        const RWIN = 0b1000_0000;
+    }
+}
+
+#[cfg(feature = "ascii")]
+impl Modifiers {
+    pub fn shift(self) -> bool {
+        self.intersects(Modifiers::LSHIFT | Modifiers::RSHIFT)
+    }
+
+    pub fn ctrl(self) -> bool {
+        self.intersects(Modifiers::LCTRL | Modifiers::RCTRL)
+    }
+
+    pub fn alt(self) -> bool {
+        self.intersects(Modifiers::LALT | Modifiers::RALT)
+    }
+}
+
+// Everything about the keyboard that outlives a single key event.
+pub struct KeyState {
+    #[cfg(feature = "ascii")]
+    modifiers: Modifiers,
+    locks: LedMask,
+    #[cfg(feature = "layouts")]
+    layout: Layout,
+    #[cfg(feature = "special-keys")]
+    function: Option<u8>, // Function key being held down.
+    #[cfg(feature = "kvm")]
+    other_locks: LedMask, // Of the XT host not being typed on.
+}
+
+impl KeyState {
+    #[cfg(feature = "ascii")]
+    const ALT: u8 = 0x11;
+    #[cfg(feature = "ascii")]
+    const LSHIFT: u8 = 0x12;
+    #[cfg(feature = "ascii")]
+    const CTRL: u8 = 0x14;
+    #[cfg(feature = "ascii")]
+    const LWIN: u8 = 0x1f;
+    #[cfg(feature = "ascii")]
+    const RWIN: u8 = 0x27;
+    const CAPS: u8 = 0x58;
+    #[cfg(feature = "ascii")]
+    const RSHIFT: u8 = 0x59;
+    const NUM: u8 = 0x77;
+    const SCROLL: u8 = 0x7e;
+
+    pub const fn new() -> KeyState {
+        KeyState {
+            #[cfg(feature = "ascii")]
+            modifiers: Modifiers::empty(),
+            locks: LedMask::empty(),
+            #[cfg(feature = "layouts")]
+            layout: Layout::Qwerty,
+            #[cfg(feature = "special-keys")]
+            function: None,
+            #[cfg(feature = "kvm")]
+            other_locks: LedMask::empty(),
+        }
+    }
+
+    // Returns the new lock state if the LEDs need to change.
+    pub fn update(&mut self, ev: KeyEvent) -> Option<LedMask> {
+        #[cfg(feature = "ascii")]
+        self.update_modifiers(ev);
+
+        // Locks toggle on release, so typematic repeat can't flip them.
+        if ev.pressed || ev.extended {
+            return None;
+        }
+
+        let l = match ev.key {
+            Self::CAPS => LedMask::CAPS,
+            Self::NUM => LedMask::NUM,
+            Self::SCROLL => LedMask::SCROLL,
+            _ => return None,
+        };
+        self.locks.toggle(l);
+        Some(self.locks)
+    }
+
+    #[cfg(feature = "ascii")]
+    fn update_modifiers(&mut self, ev: KeyEvent) {
+        // E0 12 and E0 59 are the fake shifts some keys add; ignore them.
+        let m = match (ev.key, ev.extended) {
+            (Self::LSHIFT, false) => Modifiers::LSHIFT,
+            (Self::RSHIFT, false) => Modifiers::RSHIFT,
+            (Self::CTRL, false) => Modifiers::LCTRL,
+            (Self::CTRL, true) => Modifiers::RCTRL,
+            (Self::ALT, false) => Modifiers::LALT,
+            (Self::ALT, true) => Modifiers::RALT,
+            (Self::LWIN, true) => Modifiers::LWIN,
+            (Self::RWIN, true) => Modifiers::RWIN,
+            _ => Modifiers::empty(),
+        };
+        self.modifiers.set(m, ev.pressed);
+    }
+
+    // Function keys act on the first make only; typematic repeat just sends
+    // more makes.
+    #[cfg(feature = "special-keys")]
+    pub fn repeated(&mut self, ev: KeyEvent) -> bool {
+        if !ev.pressed {
+            self.function = None;
+            return false;
+        }
+
+        let held = self.function == Some(ev.key);
+        self.function = Some(ev.key);
+        held
+    }
+
+    // Extended keys are never part of an alternate layout.
+    #[cfg(feature = "layouts")]
+    pub fn remap(&self, ev: KeyEvent) -> KeyEvent {
+        if ev.extended {
+            ev
+        } else {
+            KeyEvent {
+                key: self.layout.remap(ev.key),
+                ..ev
+            }
+        }
+    }
+
+    #[cfg(feature = "layouts")]
+    pub fn next_layout(&mut self) -> Layout {
+        self.layout = self.layout.next();
+        self.layout
+    }
+
+    // The keyboard forgot which keys are held, e.g. it was unplugged.
+    pub fn forget_keys(&mut self) {
+        #[cfg(feature = "ascii")]
+        {
+            self.modifiers = Modifiers::empty();
+        }
+        #[cfg(feature = "special-keys")]
+        {
+            self.function = None;
+        }
+    }
+
+    // The host forgot its lock state as well.
+    pub fn reset(&mut self) {
+        self.forget_keys();
+        self.locks = LedMask::empty();
+    }
+
+    // Each host keeps its own lock state while the other is typed on.
+    #[cfg(feature = "kvm")]
+    pub fn switch_locks(&mut self) {
+        core::mem::swap(&mut self.locks, &mut self.other_locks);
+    }
+
+    // The host not being typed on was reset.
+    #[cfg(feature = "kvm")]
+    pub fn reset_other(&mut self) {
+        self.other_locks = LedMask::empty();
+    }
+
+    // Modifier keys the keyboard reports as held, before any remapping.
+    #[cfg(feature = "ascii")]
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    // Lock keys that are on, as shown on the keyboard's LEDs.
+    pub fn locks(&self) -> LedMask {
+        self.locks
+    }
+}
+
+#[cfg(all(test, feature = "special-keys"))]
+mod tests {
+    use super::*;
+
+    fn key(key: u8, pressed: bool) -> KeyEvent {
+        KeyEvent {
+            key,
+            pressed,
+            extended: true,
+        }
+    }
+
+    #[test]
+    fn typematic_repeat_of_a_function_key_is_repeated() {
+        let mut keys = KeyState::new();
+
+        assert!(!keys.repeated(key(0x2f, true)));
+        assert!(keys.repeated(key(0x2f, true)));
+        assert!(keys.repeated(key(0x2f, true)));
+        assert!(!keys.repeated(key(0x2f, false)));
+        assert!(!keys.repeated(key(0x2f, true)));
+    }
+
+    #[test]
+    fn forgotten_keys_press_again() {
+        let mut keys = KeyState::new();
+
+        assert!(!keys.repeated(key(0x2f, true)));
+        keys.forget_keys();
+        assert!(!keys.repeated(key(0x2f, true)));
+    }
+
+    #[cfg(feature = "layouts")]
+    #[test]
+    fn layouts_remap_only_plain_keys() {
+        let mut keys = KeyState::new();
+        keys.next_layout();
+
+        let plain = KeyEvent {
+            extended: false,
+            ..key(0x15, true)
+        };
+        assert_eq!(keys.remap(plain).key, 0x52);
+        assert_eq!(keys.remap(key(0x15, true)).key, 0x15);
+    }
+}
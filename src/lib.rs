@@ -0,0 +1,22 @@
+// The parts of the firmware that don't touch the hardware. They build for the
+// host too, so they can be tested there; see the `test` recipe in the
+// Justfile.
+#![cfg_attr(not(test), no_std)]
+// Everything here is built in const statics with new(), and fails with ()
+// like the rest of the firmware.
+#![allow(clippy::new_without_default, clippy::result_unit_err)]
+
+pub mod keybuffer;
+pub mod keymap;
+pub mod keystate;
+pub mod set2;
+pub mod xt;
+
+#[cfg(feature = "ascii")]
+pub mod ascii;
+
+#[cfg(feature = "ps2-mouse")]
+pub mod mouse;
+
+#[cfg(any(feature = "at-host", feature = "xt-keyboard"))]
+pub mod athost;
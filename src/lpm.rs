@@ -0,0 +1,35 @@
+// The msp430 crate can turn interrupts on, but not sleep, and putting the
+// CPU to sleep takes a write to the status register. This module is the only
+// place the firmware allows `unsafe`, and asm! on msp430 needs the
+// asm_experimental_arch feature for it.
+use core::arch::asm;
+
+// Low-power modes; both wake on any interrupt.
+#[derive(Clone, Copy)]
+pub enum Lpm {
+    Lpm0, // CPU off. SMCLK, and with it TIMER_A2, keep running.
+    Lpm3, // Only ACLK runs, so TIMER_A2 stops.
+}
+
+// Status register bits.
+const GIE: u16 = 0x0008;
+const LPM0: u16 = GIE | 0x0010; // CPUOFF
+const LPM3: u16 = LPM0 | 0x00c0; // SCG0, SCG1
+
+// Turns interrupts back on, and if asked, sleeps in the same instruction so
+// no interrupt can be taken between the two. The caller must have turned
+// interrupts off itself.
+#[allow(unsafe_code)]
+#[inline(always)]
+pub fn enable_interrupts(sleep: Option<Lpm>) {
+    // SAFETY: Only GIE and the low-power bits of SR change, and the caller
+    // doesn't hold a critical section across this call, so turning
+    // interrupts on can't break one. The nop lets the write to SR take
+    // effect before anything else runs. Neither mode loses RAM or registers,
+    // and every ISR clears the low-power bits on exit (wake_cpu).
+    match sleep {
+        Some(Lpm::Lpm0) => unsafe { asm!("bis.w #{0}, r2", "nop", const LPM0) },
+        Some(Lpm::Lpm3) => unsafe { asm!("bis.w #{0}, r2", "nop", const LPM3) },
+        None => unsafe { msp430::interrupt::enable() },
+    }
+}
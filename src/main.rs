@@ -1,29 +1,131 @@
 #![no_main]
 #![no_std]
 #![feature(abi_msp430_interrupt)]
+#![feature(asm_experimental_arch)] // For entering low-power mode in lpm.
 #![deny(unsafe_code)]
 
 extern crate panic_msp430;
 
-use bit_reverse::BitwiseReverse;
 use core::cell::{Cell, RefCell};
 use msp430::{critical_section as mspcs, interrupt::CriticalSection, interrupt::Mutex};
 use msp430_rt::entry;
 use msp430g2211::{interrupt, Peripherals};
 use portable_atomic::{AtomicBool, Ordering};
 
-mod keyfsm;
-use keyfsm::{Cmd, Fsm, LedMask, ProcReply};
-
-mod keybuffer;
-use keybuffer::{KeyIn, KeyOut, KeycodeBuffer};
+use at2xt::{keybuffer, set2, xt};
+
+// An XT keyboard goes through set2::Device instead of the AT decoder.
+#[cfg(not(feature = "xt-keyboard"))]
+use at2xt::{keymap, keystate};
+#[cfg(not(feature = "xt-keyboard"))]
+use keymap::Keyboard;
+#[cfg(all(feature = "special-keys", not(feature = "xt-keyboard")))]
+use keymap::{Action, Function};
+#[cfg(not(feature = "xt-keyboard"))]
+use keystate::{KeyEvent, KeyState, LedMask};
+#[cfg(feature = "xt-keyboard")]
+use set2::Device;
+#[cfg(not(feature = "xt-keyboard"))]
+use set2::{Cmd, Decoded, Decoder};
+#[cfg(not(any(feature = "ascii", feature = "xt-keyboard")))]
+use xt::Encoder;
+use xt::XtBytes;
+
+#[cfg(feature = "ascii")]
+use at2xt::ascii::{self, Encoder};
+
+#[cfg(feature = "pcjr")]
+use keybuffer::PcjrOut as LineOut;
+#[cfg(feature = "ascii")]
+use keybuffer::UartOut as LineOut;
+#[cfg(feature = "ps2-mouse")]
+use keybuffer::UartOut;
+#[cfg(feature = "xt-keyboard")]
+use keybuffer::XtIn;
+use keybuffer::{KeyIn, Keycode, KeycodeBuffer, Status, XtBuffer};
+#[cfg(not(feature = "xt-keyboard"))]
+use keybuffer::{KeyOut, XtOut};
+// Only the XT host's clock is driven a bit at a time.
+#[cfg(not(any(feature = "pcjr", feature = "ascii", feature = "xt-keyboard")))]
+use keybuffer::XtStep;
 
 mod driver;
-use driver::Pins;
+use driver::{Pins, XtPort};
+
+// Each of these puts something else on the XT side.
+#[cfg(any(
+    all(
+        feature = "tandy1000",
+        any(
+            feature = "at-host",
+            feature = "xt-keyboard",
+            feature = "pcjr",
+            feature = "ascii"
+        )
+    ),
+    all(
+        feature = "at-host",
+        any(feature = "xt-keyboard", feature = "pcjr", feature = "ascii")
+    ),
+    all(feature = "xt-keyboard", any(feature = "pcjr", feature = "ascii")),
+    all(feature = "pcjr", feature = "ascii"),
+))]
+compile_error!(
+    "Select at most one of the tandy1000, at-host, xt-keyboard, pcjr and ascii features."
+);
+
+// The Tandy 1000's BUSY line takes the mouse's serial pin. An AT host has a
+// mouse port of its own.
+#[cfg(all(
+    feature = "ps2-mouse",
+    any(feature = "tandy1000", feature = "xt-keyboard")
+))]
+compile_error!("The ps2-mouse feature doesn't mix with tandy1000 or xt-keyboard.");
+
+// The second keyboard takes the pins of the Tandy 1000's BUSY line and the
+// mouse. Its keys can only be merged once they are decoded.
+#[cfg(all(
+    feature = "second-keyboard",
+    any(
+        feature = "tandy1000",
+        feature = "ps2-mouse",
+        feature = "at-host",
+        feature = "xt-keyboard"
+    )
+))]
+compile_error!(
+    "The second-keyboard feature doesn't mix with tandy1000, ps2-mouse, at-host or xt-keyboard."
+);
+
+// The second XT host takes every spare pin, and only IBM XT output comes in
+// pairs.
+#[cfg(all(
+    feature = "kvm",
+    any(
+        feature = "tandy1000",
+        feature = "at-host",
+        feature = "xt-keyboard",
+        feature = "pcjr",
+        feature = "ascii",
+        feature = "ps2-mouse",
+        feature = "second-keyboard"
+    )
+))]
+compile_error!("The kvm feature only works with IBM XT output.");
+
+#[cfg(feature = "ps2-mouse")]
+use at2xt::mouse::{self, Mouse, MouseEvent};
+
+#[cfg(any(feature = "at-host", feature = "xt-keyboard"))]
+use at2xt::athost::AtLink;
 
 mod peripheral;
 use peripheral::At2XtPeripherals;
 
+mod lpm;
+use lpm::Lpm;
+
+#[cfg(not(feature = "xt-keyboard"))]
 macro_rules! delay_us {
     ($u:expr) => {
         // Timer is 100000 Hz, thus granularity of 10us.
@@ -31,55 +133,505 @@ macro_rules! delay_us {
     };
 }
 
+// Waiting on a busy XT host, in 10us timer ticks. Bit timing is in
+// xt::TIMING.
+#[cfg(not(feature = "xt-keyboard"))]
+const XT_HOST_POLL: u16 = 10;
+
+// How far out to put an XT edge whose time already passed, in 10us timer
+// ticks. Enough that the timer can't tick past it before we write it.
+const XT_TX_LATE: u16 = 2;
+
+// The BIOS holds the XT clock low for ~20ms to reset the keyboard. Anything
+// shorter than 10ms (in 10us timer ticks) is a glitch, or our own clock.
+#[cfg(not(feature = "xt-keyboard"))]
+const XT_RESET_MIN: u16 = 1000;
+
+// An XT clock held low longer than this (200ms, in 10us timer ticks) is a
+// host that is switched off, not one resetting the keyboard.
+const XT_HOST_GONE: u16 = 20000;
+
+// What to do with keys while the XT host is off.
+#[allow(dead_code)]
+enum HostAbsent {
+    Discard, // The host starts from scratch when it powers on anyway.
+    Hold,    // Leave them in the keyboard until the host comes back.
+}
+
+const HOST_ABSENT_POLICY: HostAbsent = HostAbsent::Discard;
+
+// PS/2 mouse reports a second.
+#[cfg(feature = "ps2-mouse")]
+const MOUSE_SAMPLE_RATE: u8 = 20;
+
+// Transfers the keyboard may miss in a row before it counts as unplugged.
+#[cfg(not(feature = "xt-keyboard"))]
+const AT_TX_TRIES: u8 = 3;
+
+// Longest a keyboard may take to clock in a byte from the host, in 10us
+// timer ticks.
+#[cfg(not(feature = "xt-keyboard"))]
+const AT_TX_TIMEOUT: u16 = 2000;
+
+// OUT_BUFFER fill levels. Past XT_HOLD, the AT keyboard is inhibited and
+// buffers keys itself until the host catches up to XT_RESUME. Input is only
+// decoded while the longest thing a single key can send still fits.
+const XT_HOLD: u8 = 5;
+#[cfg(not(feature = "xt-keyboard"))]
+const XT_RESUME: u8 = 2;
+#[cfg(not(feature = "xt-keyboard"))]
+const XT_ROOM_NEEDED: u8 = Encoder::LONGEST;
+#[cfg(feature = "xt-keyboard")]
+const XT_ROOM_NEEDED: u8 = Device::LONGEST;
+
+// IN_BUFFER fill levels. Past IN_HOLD, the keyboard is inhibited until the
+// main loop works the buffer down to IN_RESUME, so keys are never dropped.
+#[cfg(not(feature = "xt-keyboard"))]
+const IN_HOLD: u8 = 12;
+#[cfg(not(feature = "xt-keyboard"))]
+const IN_RESUME: u8 = 4;
+
+// The same for the second keyboard's half-sized buffer.
+#[cfg(feature = "second-keyboard")]
+const SECOND_HOLD: u8 = 4;
+#[cfg(feature = "second-keyboard")]
+const SECOND_RESUME: u8 = 2;
+
+// The XT host holding XT_SENSE low: since when, and how many times TIMER_A2
+// has wrapped since.
+#[derive(Clone, Copy)]
+struct LowPulse {
+    start: u16,
+    wraps: u8,
+}
+
+impl LowPulse {
+    #[cfg(not(feature = "xt-keyboard"))]
+    const fn new(now: u16) -> LowPulse {
+        LowPulse {
+            start: now,
+            wraps: 0,
+        }
+    }
+
+    // How long the pulse has lasted, in 10us timer ticks. Saturates instead of
+    // wrapping, so a host held off for seconds isn't a glitch.
+    fn ticks(self, now: u16) -> u16 {
+        match self.wraps {
+            0 => now.wrapping_sub(self.start),
+            1 if now < self.start => now.wrapping_sub(self.start),
+            _ => u16::MAX,
+        }
+    }
+}
+
 static TIMEOUT: AtomicBool = AtomicBool::new(false);
+static XT_BUSY: AtomicBool = AtomicBool::new(false);
+static HOST_RESET: AtomicBool = AtomicBool::new(false);
+static HOST_ABSENT: AtomicBool = AtomicBool::new(false);
+#[cfg(not(feature = "xt-keyboard"))]
+static KEYBOARD_ABSENT: AtomicBool = AtomicBool::new(false);
 static HOST_MODE: AtomicBool = AtomicBool::new(false);
+#[cfg(not(feature = "xt-keyboard"))]
 static DEVICE_ACK: AtomicBool = AtomicBool::new(false);
-
-static IN_BUFFER: Mutex<RefCell<KeycodeBuffer>> = Mutex::new(RefCell::new(KeycodeBuffer::new()));
+// Reasons to keep the AT keyboards inhibited.
+#[cfg(not(feature = "xt-keyboard"))]
+static XT_FULL: AtomicBool = AtomicBool::new(false);
+#[cfg(not(feature = "xt-keyboard"))]
+static IN_FULL: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "second-keyboard")]
+static SECOND_FULL: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "second-keyboard")]
+static SECOND_ABSENT: AtomicBool = AtomicBool::new(false);
+
+// Lock-free; the PORT1 ISR puts and the main loop takes.
+static IN_BUFFER: KeycodeBuffer<16> = KeycodeBuffer::new();
 static KEY_IN: Mutex<Cell<KeyIn>> = Mutex::new(Cell::new(KeyIn::new()));
+#[cfg(not(feature = "xt-keyboard"))]
 static KEY_OUT: Mutex<Cell<KeyOut>> = Mutex::new(Cell::new(KeyOut::new()));
-
-#[interrupt]
+static OUT_BUFFER: Mutex<RefCell<XtBuffer>> = Mutex::new(RefCell::new(XtBuffer::new()));
+#[cfg(not(feature = "xt-keyboard"))]
+static XT_OUT: Mutex<Cell<XtOut>> = Mutex::new(Cell::new(XtOut::new()));
+static XT_RESET_PULSE: Mutex<Cell<Option<LowPulse>>> = Mutex::new(Cell::new(None));
+// Which host XT_OUT is clocking a byte to.
+#[cfg(not(feature = "xt-keyboard"))]
+static XT_TX_PORT: Mutex<Cell<XtPort>> = Mutex::new(Cell::new(XtPort::First));
+
+// The second XT host's counterparts of HOST_RESET, HOST_ABSENT, OUT_BUFFER
+// and XT_RESET_PULSE, and which host gets the keys.
+#[cfg(feature = "kvm")]
+static SECOND_HOST_RESET: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "kvm")]
+static SECOND_HOST_ABSENT: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "kvm")]
+static SECOND_OUT_BUFFER: Mutex<RefCell<XtBuffer>> = Mutex::new(RefCell::new(XtBuffer::new()));
+#[cfg(feature = "kvm")]
+static SECOND_RESET_PULSE: Mutex<Cell<Option<LowPulse>>> = Mutex::new(Cell::new(None));
+#[cfg(feature = "kvm")]
+static ACTIVE_HOST: Mutex<Cell<XtPort>> = Mutex::new(Cell::new(XtPort::First));
+
+// Set for good once the host turns out to be an AT; from then on bytes pass
+// through untouched in both directions.
+#[cfg(feature = "at-host")]
+static AT_HOST: AtomicBool = AtomicBool::new(false);
+// Lock-free; the TIMERA1 ISR puts and the main loop takes.
+#[cfg(any(feature = "at-host", feature = "xt-keyboard"))]
+static HOST_IN: KeycodeBuffer<4> = KeycodeBuffer::new();
+#[cfg(any(feature = "at-host", feature = "xt-keyboard"))]
+static AT_LINK: Mutex<Cell<AtLink>> = Mutex::new(Cell::new(AtLink::new()));
+#[cfg(feature = "pcjr")]
+static LINE_OUT: Mutex<Cell<LineOut>> = Mutex::new(Cell::new(LineOut::new()));
+#[cfg(feature = "ascii")]
+static LINE_OUT: Mutex<Cell<LineOut>> = Mutex::new(Cell::new(LineOut::new(ascii::BAUD, 8)));
+// The aux port's counterparts of HOST_MODE, DEVICE_ACK, IN_BUFFER, KEY_IN
+// and KEY_OUT.
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+static AUX_HOST_MODE: AtomicBool = AtomicBool::new(false);
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+static AUX_ACK: AtomicBool = AtomicBool::new(false);
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+static AUX_BUFFER: KeycodeBuffer<8> = KeycodeBuffer::new();
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+static AUX_IN: Mutex<Cell<KeyIn>> = Mutex::new(Cell::new(KeyIn::new()));
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+static AUX_OUT: Mutex<Cell<KeyOut>> = Mutex::new(Cell::new(KeyOut::new()));
+// The serial mouse driver wants to hear an M.
+#[cfg(feature = "ps2-mouse")]
+static RTS_RAISED: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "xt-keyboard")]
+static XT_IN: Mutex<Cell<XtIn>> = Mutex::new(Cell::new(XtIn::new()));
+
+#[interrupt(wake_cpu)]
 fn TIMERA0(cs: CriticalSection) {
     TIMEOUT.store(true, Ordering::SeqCst);
 
     // Use unwrap b/c within interrupt handlers, if we can't get access to
     // peripherals right away, there's no point in continuing.
     let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).unwrap();
-    // Writing 0x0000 stops Timer in MC1.
-    timer.taccr0.write(|w| w.taccr0().bits(0x0000));
+    // The timer keeps running for the XT transmitter; just stop interrupting.
+    timer.tacctl0.write(|w| w.ccie().clear_bit());
     // CCIFG will be reset when entering interrupt; no need to clear it.
     // Nesting is disabled, and chances of receiving second CCIFG in the ISR
     // are nonexistant.
 }
 
-#[interrupt]
-fn PORT1(cs: CriticalSection) {
+// Sends bytes from OUT_BUFFER to the XT host, one clock edge per interrupt.
+#[cfg(not(any(feature = "xt-keyboard", feature = "pcjr", feature = "ascii")))]
+#[interrupt(wake_cpu)]
+fn TIMERA1(cs: CriticalSection) {
+    let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).unwrap();
     let port = At2XtPeripherals::periph_ref(cs).unwrap();
 
-    if HOST_MODE.load(Ordering::SeqCst) {
-        let mut keyout = KEY_OUT.borrow(cs).get();
+    if !ccr1_due(cs, timer) {
+        return;
+    }
 
-        if let Some(k) = keyout.shift_out() {
-            if k {
-                driver::set(port, Pins::AT_DATA);
+    #[cfg(feature = "at-host")]
+    if AT_HOST.load(Ordering::SeqCst) {
+        let wait = at_link_step(cs, Pins::XT_CLK, Pins::XT_DATA);
+        schedule_xt_tx(timer, wait);
+        return;
+    }
+
+    let mut xt_out = XT_OUT.borrow(cs).get();
+    let xt = XT_TX_PORT.borrow(cs).get();
+
+    let wait = match xt_out.step() {
+        Some(XtStep::ClkLow(bit)) => {
+            if bit {
+                driver::set(port, xt.data());
             } else {
-                driver::unset(port, Pins::AT_DATA);
+                driver::unset(port, xt.data());
             }
 
-            // Immediately after sending out the Stop Bit, we should release the lines.
-            if keyout.is_empty() {
-                driver::at_idle(port);
+            driver::unset(port, xt.clk());
+            Some(xt::TIMING.clk_low)
+        }
+        Some(XtStep::ClkHigh) => {
+            driver::set(port, xt.clk());
+            Some(xt::TIMING.clk_high)
+        }
+        None => {
+            driver::xt_in(port, xt);
+
+            // Hosts take turns, so neither can keep the other waiting.
+            let mut wait = None;
+
+            for next in xt.turns() {
+                match start_xt_byte(cs, next, &mut xt_out) {
+                    Some(ticks) if !xt_out.is_empty() => {
+                        XT_TX_PORT.borrow(cs).set(next);
+                        wait = Some(ticks);
+                        break;
+                    }
+                    Some(ticks) => wait = Some(ticks),
+                    None => {}
+                }
             }
-        } else {
-            // TODO: Is it possible to get a spurious clock interrupt and
-            // thus skip this logic?
-            if driver::is_unset(port, Pins::AT_DATA) {
-                DEVICE_ACK.store(true, Ordering::SeqCst);
-                keyout.clear();
+
+            wait
+        }
+    };
+
+    XT_OUT.borrow(cs).set(xt_out);
+    schedule_xt_tx(timer, wait);
+}
+
+// Starts the host's next byte on xt_out, if it is listening. Returns when to
+// look again, or None if the host has nothing to send.
+#[cfg(not(any(feature = "xt-keyboard", feature = "pcjr", feature = "ascii")))]
+fn start_xt_byte(cs: CriticalSection, xt: XtPort, xt_out: &mut XtOut) -> Option<u16> {
+    let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).unwrap();
+    let port = At2XtPeripherals::periph_ref(cs).unwrap();
+
+    // The host cannot send data; the only communication it can do with the
+    // micro is pull the CLK (reset) and DATA (shift register full) low. Wait
+    // for the host to release the lines.
+    #[cfg(not(feature = "tandy1000"))]
+    let host_busy = driver::is_unset(port, xt.clk()) || driver::is_unset(port, xt.data());
+    // A Tandy 1000 has a BUSY line of its own instead, low while it can't
+    // take a byte.
+    #[cfg(feature = "tandy1000")]
+    let host_busy = driver::is_unset(port, xt.clk()) || driver::is_unset(port, Pins::TANDY_BUSY);
+
+    check_host_gone(cs, xt, timer.tar.read().bits());
+
+    let discard =
+        host_absent(xt).load(Ordering::SeqCst) && matches!(HOST_ABSENT_POLICY, HostAbsent::Discard);
+
+    match out_buffer(xt).borrow(cs).try_borrow_mut() {
+        Ok(ref b) if b.is_empty() => None,
+        Ok(mut b) if discard => {
+            b.flush();
+            None
+        }
+        Ok(_) if host_busy => Some(XT_HOST_POLL),
+        Ok(mut b) => b.take().and_then(|byte| {
+            driver::xt_out(port, xt);
+            xt_out
+                .put(byte, xt::TIMING.two_start_bits)
+                .ok()
+                .map(|_| xt::TIMING.clk_high + xt::TIMING.byte_gap)
+        }),
+        Err(_) => Some(XT_HOST_POLL),
+    }
+}
+
+// Runs TIMERA1 again after wait ticks, or lets the transmitter go idle.
+fn schedule_xt_tx(timer: &msp430g2211::TIMER_A2, wait: Option<u16>) {
+    if let Some(ticks) = wait {
+        let now = timer.tar.read().bits();
+        let mut at = timer.taccr1.read().bits().wrapping_add(ticks);
+        let left = at.wrapping_sub(now);
+
+        // If we got here late, the edge is already in the past and the timer
+        // wouldn't match it again until it wrapped; make it ASAP instead.
+        if left == 0 || left > u16::MAX / 2 {
+            at = now.wrapping_add(XT_TX_LATE);
+        }
+
+        timer.taccr1.write(|w| w.taccr1().bits(at));
+        // Writing the control register also clears CCIFG.
+        timer.tacctl1.write(|w| w.ccie().set_bit());
+    } else {
+        timer.tacctl1.write(|w| w.ccie().clear_bit());
+        XT_BUSY.store(false, Ordering::SeqCst);
+    }
+}
+
+// TIMERA1 also takes TIMER_A2 overflows, which time XT_SENSE pulses too long
+// for a single 16-bit count. Returns whether CCR1 is what's due.
+fn ccr1_due(cs: CriticalSection, timer: &msp430g2211::TIMER_A2) -> bool {
+    count_timer_wraps(cs, timer);
+
+    let cctl1 = timer.tacctl1.read();
+    cctl1.ccie().bit_is_set() && cctl1.ccifg().bit_is_set()
+}
+
+// A host is noticed going away even with nothing to send it, within two
+// wraps of its clock going low.
+fn count_timer_wraps(cs: CriticalSection, timer: &msp430g2211::TIMER_A2) {
+    if timer.tactl.read().taifg().bit_is_clear() {
+        return;
+    }
+
+    timer.tactl.modify(|_, w| w.taifg().clear_bit());
+    let now = timer.tar.read().bits();
+
+    for xt in XtPort::ALL {
+        let pulse = reset_pulse(xt).borrow(cs);
+
+        if let Some(mut p) = pulse.get() {
+            p.wraps = p.wraps.saturating_add(1);
+            pulse.set(Some(p));
+            check_host_gone(cs, xt, now);
+        }
+    }
+}
+
+// A powered-off host holds its lines low for good.
+fn check_host_gone(cs: CriticalSection, xt: XtPort, now: u16) {
+    if reset_pulse(xt)
+        .borrow(cs)
+        .get()
+        .is_some_and(|p| p.ticks(now) >= XT_HOST_GONE)
+    {
+        host_absent(xt).store(true, Ordering::SeqCst);
+    }
+}
+
+// Sends bytes from OUT_BUFFER down a single line instead, the PCjr link or
+// a serial port, one level per interrupt. The host can't ask us to wait.
+#[cfg(any(feature = "pcjr", feature = "ascii"))]
+#[interrupt(wake_cpu)]
+fn TIMERA1(cs: CriticalSection) {
+    let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).unwrap();
+    let port = At2XtPeripherals::periph_ref(cs).unwrap();
+
+    if !ccr1_due(cs, timer) {
+        return;
+    }
+
+    let mut line_out = LINE_OUT.borrow(cs).get();
+
+    let wait = match line_out.step() {
+        Some((level, ticks)) => {
+            if level {
+                driver::set(port, Pins::XT_DATA);
+            } else {
+                driver::unset(port, Pins::XT_DATA);
+            }
+
+            Some(ticks)
+        }
+        None => match OUT_BUFFER.borrow(cs).try_borrow_mut() {
+            Ok(mut b) => b.take().and_then(|byte| line_out.put(byte).ok().map(|_| 1)),
+            Err(_) => Some(XT_HOST_POLL),
+        },
+    };
+
+    LINE_OUT.borrow(cs).set(line_out);
+    schedule_xt_tx(timer, wait);
+}
+
+// Talks to the AT host on the AT port instead, sending bytes from OUT_BUFFER.
+#[cfg(feature = "xt-keyboard")]
+#[interrupt(wake_cpu)]
+fn TIMERA1(cs: CriticalSection) {
+    let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).unwrap();
+
+    if !ccr1_due(cs, timer) {
+        return;
+    }
+
+    let wait = at_link_step(cs, Pins::AT_CLK, Pins::AT_DATA);
+    schedule_xt_tx(timer, wait);
+}
+
+// Clocks the AT host's side of a port, half a bit per interrupt.
+#[cfg(any(feature = "at-host", feature = "xt-keyboard"))]
+fn at_link_step(cs: CriticalSection, clk: Pins, data: Pins) -> Option<u16> {
+    let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).unwrap();
+    let port = At2XtPeripherals::periph_ref(cs).unwrap();
+    let now = timer.tar.read().bits();
+    let mut link = AT_LINK.borrow(cs).get();
+
+    let step = link.step(
+        driver::is_set(port, clk),
+        driver::is_set(port, data),
+        now,
+        || {
+            OUT_BUFFER
+                .borrow(cs)
+                .try_borrow_mut()
+                .ok()
+                .and_then(|mut b| b.take())
+        },
+    );
+
+    AT_LINK.borrow(cs).set(link);
+    driver::open_drain(port, data, step.data);
+    driver::open_drain(port, clk, step.clk);
+
+    if let Some(k) = step.received {
+        // The host waits for our answer before sending more; it can't
+        // overrun this.
+        #[allow(clippy::let_underscore_must_use)]
+        {
+            let _ = HOST_IN.put(k);
+        }
+    }
+
+    step.wait
+}
+
+// The XT keyboard's clock arrives on XT_SENSE, and an AT host letting go of
+// AT_CLK may be asking to send.
+#[cfg(feature = "xt-keyboard")]
+#[interrupt(wake_cpu)]
+fn PORT1(cs: CriticalSection) {
+    let port = At2XtPeripherals::periph_ref(cs).unwrap();
+    let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).unwrap();
+
+    if driver::xt_sense_int_pending(port, XtPort::First) {
+        driver::clear_xt_sense_int(port, XtPort::First);
+
+        let now = timer.tar.read().bits();
+        let mut xt_in = XT_IN.borrow(cs).get();
+
+        if let Some(b) = xt_in.shift_in(driver::is_set(port, Pins::XT_DATA), now) {
+            // The XT keyboard can't be held off; the main loop tells the
+            // host when this overflows.
+            #[allow(clippy::let_underscore_must_use)]
+            {
+                let _ = IN_BUFFER.put(Keycode {
+                    byte: b,
+                    status: Status::empty(),
+                });
             }
         }
 
+        XT_IN.borrow(cs).set(xt_in);
+    }
+
+    if driver::at_clk_int_pending(port) {
+        driver::clear_at_clk_int(port);
+        start_xt_tx(timer);
+    }
+}
+
+#[cfg(not(feature = "xt-keyboard"))]
+#[interrupt(wake_cpu)]
+fn PORT1(cs: CriticalSection) {
+    let port = At2XtPeripherals::periph_ref(cs).unwrap();
+
+    for xt in XtPort::ALL {
+        if driver::xt_sense_int_pending(port, xt) {
+            driver::clear_xt_sense_int(port, xt);
+            xt_sense_changed(cs, xt);
+        }
+    }
+
+    #[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+    if driver::aux_clk_int_pending(port) {
+        driver::clear_aux_clk_int(port);
+        aux_clk_edge(cs);
+    }
+
+    if !driver::at_clk_int_pending(port) {
+        return;
+    }
+
+    let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).unwrap();
+    let now = timer.tar.read().bits();
+
+    if HOST_MODE.load(Ordering::SeqCst) {
+        let mut keyout = KEY_OUT.borrow(cs).get();
+
+        if shift_out_edge(port, Pins::AT_DATA, &mut keyout, now, driver::at_idle) {
+            DEVICE_ACK.store(true, Ordering::SeqCst);
+        }
+
         KEY_OUT.borrow(cs).set(keyout);
     } else {
         let mut keyin = KEY_IN.borrow(cs).get();
@@ -87,23 +639,32 @@ fn PORT1(cs: CriticalSection) {
         // Are the buffer functions safe in nested interrupts? Is it possible to use tokens/manual
         // sync for nested interrupts while not giving up safety?
         // Example: Counter for nest level when updating buffers. If it's ever more than one, panic.
-        if keyin.shift_in(driver::is_set(port, Pins::AT_DATA)).is_err() {
+        if keyin
+            .shift_in(driver::is_set(port, Pins::AT_DATA), now)
+            .is_err()
+        {
             driver::at_inhibit(port); // Ask keyboard to not send anything while processing keycode.
 
             if let Some(k) = keyin.take() {
-                if let Ok(mut b) = IN_BUFFER.borrow(cs).try_borrow_mut() {
-                    // Dropping keys when the buffer is full is in line
-                    // with what AT/XT hosts do. Saves 2 bytes on panic :)!
-                    #[allow(clippy::let_underscore_must_use)]
-                    {
-                        let _ = b.put(k);
-                    }
+                // Dropping keys when the buffer is full is in line
+                // with what AT/XT hosts do. Saves 2 bytes on panic :)!
+                // IN_HOLD keeps it from getting that far; if it does,
+                // the buffer remembers to send an overrun.
+                #[allow(clippy::let_underscore_must_use)]
+                {
+                    let _ = IN_BUFFER.put(k);
+                }
+
+                if IN_BUFFER.len() >= IN_HOLD {
+                    IN_FULL.store(true, Ordering::SeqCst);
                 }
             }
 
             keyin.clear();
 
-            driver::at_idle(port);
+            if !keyboard_held() {
+                driver::at_idle(port);
+            }
         }
 
         KEY_IN.borrow(cs).set(keyin);
@@ -112,6 +673,169 @@ fn PORT1(cs: CriticalSection) {
     driver::clear_at_clk_int(port);
 }
 
+// Puts the next bit of a frame for an AT device on data, at a falling edge
+// of its clock. Returns true once the device has acknowledged the frame.
+#[cfg(not(feature = "xt-keyboard"))]
+fn shift_out_edge(
+    port: &msp430g2211::PORT_1_2,
+    data: Pins,
+    keyout: &mut KeyOut,
+    now: u16,
+    release: fn(&msp430g2211::PORT_1_2),
+) -> bool {
+    if !keyout.edge(now) {
+        // Too soon after the last edge to be the device's clock; a glitch
+        // would otherwise shift a bit out early and the ACK would be read at
+        // the wrong edge.
+        false
+    } else if let Some(k) = keyout.shift_out() {
+        if k {
+            driver::set(port, data);
+        } else {
+            driver::unset(port, data);
+        }
+
+        // Immediately after sending out the Stop Bit, we should release the lines.
+        if keyout.is_empty() {
+            release(port);
+        }
+
+        false
+    } else if driver::is_unset(port, data) {
+        keyout.clear();
+        true
+    } else {
+        // No ACK means the device gave up on the byte; the sender times out
+        // and tries again.
+        false
+    }
+}
+
+// Clocks a bit of a frame on the aux port in or out.
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+fn aux_clk_edge(cs: CriticalSection) {
+    let port = At2XtPeripherals::periph_ref(cs).unwrap();
+    let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).unwrap();
+    let now = timer.tar.read().bits();
+
+    if AUX_HOST_MODE.load(Ordering::SeqCst) {
+        let mut aux_out = AUX_OUT.borrow(cs).get();
+
+        if shift_out_edge(port, driver::AUX_DATA, &mut aux_out, now, driver::aux_idle) {
+            AUX_ACK.store(true, Ordering::SeqCst);
+        }
+
+        AUX_OUT.borrow(cs).set(aux_out);
+    } else {
+        let mut aux_in = AUX_IN.borrow(cs).get();
+
+        if aux_in
+            .shift_in(driver::is_set(port, driver::AUX_DATA), now)
+            .is_err()
+        {
+            // A full buffer remembers to send an overrun, or to resync the
+            // mouse.
+            if let Some(k) = aux_in.take() {
+                #[allow(clippy::let_underscore_must_use)]
+                {
+                    let _ = AUX_BUFFER.put(k);
+                }
+
+                #[cfg(feature = "second-keyboard")]
+                if AUX_BUFFER.len() >= SECOND_HOLD {
+                    SECOND_FULL.store(true, Ordering::SeqCst);
+                }
+            }
+
+            aux_in.clear();
+
+            if aux_held() {
+                driver::aux_inhibit(port);
+            }
+        }
+
+        AUX_IN.borrow(cs).set(aux_in);
+    }
+}
+
+// Drivers raise RTS to look for a serial mouse, which answers with M.
+#[cfg(feature = "ps2-mouse")]
+#[interrupt(wake_cpu)]
+fn PORT2(cs: CriticalSection) {
+    let port = At2XtPeripherals::periph_ref(cs).unwrap();
+
+    if driver::mouse_rts_int_pending(port) {
+        driver::clear_mouse_rts_int(port);
+        RTS_RAISED.store(true, Ordering::SeqCst);
+    }
+}
+
+// Times XT_SENSE low pulses. On the release of a long enough one, the host
+// gets 0xAA as soon as it is listening, ahead of anything still queued, and
+// the main loop is told to reset everything else.
+#[cfg(not(feature = "xt-keyboard"))]
+fn xt_sense_changed(cs: CriticalSection, xt: XtPort) {
+    let port = At2XtPeripherals::periph_ref(cs).unwrap();
+    let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).unwrap();
+    count_timer_wraps(cs, timer);
+    let now = timer.tar.read().bits();
+    let start = reset_pulse(xt).borrow(cs);
+
+    if driver::is_unset(port, xt.sense()) {
+        if start.get().is_none() {
+            start.set(Some(LowPulse::new(now)));
+        }
+
+        driver::xt_sense_on_rise(port, xt);
+    } else {
+        // An AT host asks to send by letting go of CLK, briefly held low,
+        // while holding DATA low. An XT host only holds DATA low while busy,
+        // which it isn't after a short pulse of its own, and our own XT clock
+        // runs with XT_BUSY set.
+        #[cfg(feature = "at-host")]
+        if AT_HOST.load(Ordering::SeqCst)
+            || (driver::is_unset(port, xt.data())
+                && !XT_BUSY.load(Ordering::SeqCst)
+                && start.get().is_some_and(|p| p.ticks(now) < XT_RESET_MIN))
+        {
+            // Inhibits aren't resets; AT hosts send the keyboard a command.
+            AT_HOST.store(true, Ordering::SeqCst);
+            host_absent(xt).store(false, Ordering::SeqCst);
+            start.set(None);
+            start_xt_tx(timer);
+            driver::xt_sense_on_fall(port, xt);
+            return;
+        }
+
+        // A host coming back on gets the same 0xAA as one that reset us.
+        let was_absent = host_absent(xt).swap(false, Ordering::SeqCst);
+
+        if let Some(p) = start.take() {
+            if was_absent || p.ticks(now) >= XT_RESET_MIN {
+                // A byte going to the other host is left to finish.
+                if XT_TX_PORT.borrow(cs).get() == xt {
+                    XT_OUT.borrow(cs).set(XtOut::new());
+                }
+
+                if let Ok(mut b) = out_buffer(xt).borrow(cs).try_borrow_mut() {
+                    b.flush();
+                    // The Tandy 1000 BIOS doesn't wait for a self test.
+                    #[cfg(not(any(feature = "tandy1000", feature = "ascii")))]
+                    #[allow(clippy::let_underscore_must_use)]
+                    {
+                        let _ = b.put_all([Encoder::SELF_TEST_PASSED]);
+                        start_xt_tx(timer);
+                    }
+                }
+
+                host_reset(xt).store(true, Ordering::SeqCst);
+            }
+        }
+
+        driver::xt_sense_on_fall(port, xt);
+    }
+}
+
 fn init(cs: CriticalSection) {
     let p = Peripherals::take().unwrap();
 
@@ -120,6 +844,17 @@ fn init(cs: CriticalSection) {
         .write(|w| w.wdtpw().password().wdthold().set_bit());
 
     driver::idle(&p.PORT_1_2);
+    #[cfg(feature = "xt-keyboard")]
+    driver::at_clk_on_rise(&p.PORT_1_2);
+    // The PCjr link rests low, and a serial line high.
+    #[cfg(feature = "pcjr")]
+    driver::xt_line_out(&p.PORT_1_2, false);
+    #[cfg(feature = "ascii")]
+    driver::xt_line_out(&p.PORT_1_2, true);
+    #[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+    driver::aux_init(&p.PORT_1_2);
+    #[cfg(feature = "ps2-mouse")]
+    driver::mouse_init(&p.PORT_1_2);
 
     let calcb1 = p.CALIBRATION_DATA.calbc1_1mhz.read().calbc1_1mhz().bits();
     let caldco = p.CALIBRATION_DATA.calbc1_1mhz.read().calbc1_1mhz().bits();
@@ -155,11 +890,18 @@ fn init(cs: CriticalSection) {
     });
     p.SYSTEM_CLOCK.bcsctl2.write(|w| w.divs().divs_2()); // Divide submain clock by 4, nominally 400kHz.
 
-    p.TIMER_A2.taccr0.write(|w| w.taccr0().bits(0x0000));
-    p.TIMER_A2
-        .tactl
-        .write(|w| w.tassel().tassel_2().id().id_2().mc().mc_1()); // Divide by 4, use submain clock (100kHz).
-    p.TIMER_A2.tacctl0.write(|w| w.ccie().set_bit());
+    // CCR0 times delays and CCR1 times XT bits, both relative to a
+    // free-running count. Its overflows time long XT_SENSE pulses.
+    p.TIMER_A2.tactl.write(|w| {
+        w.tassel()
+            .tassel_2()
+            .id()
+            .id_2()
+            .mc()
+            .mc_2()
+            .taie()
+            .set_bit()
+    }); // Divide by 4, use submain clock (100kHz).
 
     let shared = At2XtPeripherals {
         port: p.PORT_1_2,
@@ -167,187 +909,659 @@ fn init(cs: CriticalSection) {
     };
 
     At2XtPeripherals::init(shared, cs).unwrap();
+
+    // The host may already be holding the keyboard in reset at power-on.
+    #[cfg(not(any(feature = "xt-keyboard", feature = "pcjr", feature = "ascii")))]
+    for xt in XtPort::ALL {
+        xt_sense_changed(cs, xt);
+    }
 }
 
+#[cfg(not(feature = "xt-keyboard"))]
 #[entry(interrupt_enable(pre_interrupt = init))]
 fn main() -> ! {
     send_byte_to_at_keyboard(Cmd::RESET).unwrap();
+    #[cfg(feature = "second-keyboard")]
+    send_byte_to_second_keyboard(Cmd::RESET).unwrap();
+    // Like a real XT keyboard, pass the self test at power-on too.
+    #[cfg(not(any(feature = "tandy1000", feature = "pcjr", feature = "ascii")))]
+    for xt in XtPort::ALL {
+        send_to_host(xt, XtBytes::one(Encoder::SELF_TEST_PASSED)).unwrap();
+    }
 
-    let mut loop_cmd: Cmd;
-    let mut loop_reply: ProcReply = ProcReply::init();
-    let mut fsm_driver: Fsm = Fsm::start();
+    let mut decoder = Decoder::new();
+    let mut encoder = Encoder::new();
+    let mut keys = KeyState::new();
+    // Each keyboard is in the middle of its own sequence.
+    #[cfg(feature = "second-keyboard")]
+    let mut second_decoder = Decoder::new();
+
+    // It answers with a self test, and gets set up then.
+    #[cfg(feature = "ps2-mouse")]
+    let mut mouse = Mouse::new();
+    #[cfg(feature = "ps2-mouse")]
+    {
+        command_mouse(&mut mouse, Cmd::RESET).unwrap();
+        send_to_serial_mouse(&[mouse::SERIAL_ID]).unwrap();
+    }
 
     loop {
-        // Run state machine/send reply. Receive new cmd.
-        loop_cmd = fsm_driver.run(&loop_reply).unwrap();
-
-        loop_reply = match loop_cmd {
-            Cmd::ClearBuffer => {
-                mspcs::with(|cs| {
-                    // XXX: IN_BUFFER.borrow(cs).borrow_mut() and
-                    // IN_BUFFER.borrow(cs).try_borrow_mut().unwrap()
-                    // bring in dead formatting code! Use explicit
-                    // if-let for now and handle errors by doing nothing.
-
-                    if let Ok(mut b) = IN_BUFFER.borrow(cs).try_borrow_mut() {
-                        b.flush()
-                    }
-                });
-                ProcReply::ClearedBuffer
+        match wait_for_input() {
+            // The AT host understands the keyboard as it is.
+            #[cfg(feature = "at-host")]
+            Input::Key(k) if AT_HOST.load(Ordering::SeqCst) => {
+                if k.is_valid() {
+                    send_byte_to_pc(k.byte).unwrap();
+                }
             }
-            Cmd::ToggleLed(m) => {
-                toggle_leds(m).unwrap();
-                ProcReply::LedToggled(m)
+            #[cfg(feature = "at-host")]
+            Input::HostByte(b) => send_byte_to_at_keyboard(b).unwrap(),
+            #[cfg(feature = "ps2-mouse")]
+            Input::Mouse(k) => handle_mouse(k, &mut mouse).unwrap(),
+            #[cfg(feature = "ps2-mouse")]
+            Input::MouseProbe => send_to_serial_mouse(&[mouse::SERIAL_ID]).unwrap(),
+            Input::Key(k) => {
+                handle_keycode(k, Keyboard::First, &mut decoder, &mut encoder, &mut keys).unwrap()
             }
-            Cmd::SendXtKey(k) => {
-                send_byte_to_pc(k).unwrap();
-                ProcReply::SentKey(k)
+            #[cfg(feature = "second-keyboard")]
+            Input::SecondKey(k) => handle_keycode(
+                k,
+                Keyboard::Second,
+                &mut second_decoder,
+                &mut encoder,
+                &mut keys,
+            )
+            .unwrap(),
+            // xt_sense_changed already answered the host. The one not being
+            // typed on only forgets its locks.
+            #[cfg(feature = "kvm")]
+            Input::HostReset(xt) if xt != active_host() => keys.reset_other(),
+            Input::HostReset(_) => {
+                reset_keyboard(Keyboard::First, &mut decoder).unwrap();
+                #[cfg(feature = "second-keyboard")]
+                reset_keyboard(Keyboard::Second, &mut second_decoder).unwrap();
+                keys.reset();
+                encoder.reset();
             }
-            Cmd::WaitForKey => {
-                // The micro spends the majority of its life idle. It is possible for the host PC and
-                // the keyboard to send data to the micro at the same time. To keep control flow simple,
-                // the micro will only respond to host PC acknowledge requests if its idle.
-                fn reset_requested() -> bool {
-                    mspcs::with(|cs| {
-                        let port = At2XtPeripherals::periph_ref(cs).unwrap();
-
-                        driver::is_unset(port, Pins::XT_SENSE)
-                    })
-                }
+        }
+    }
+}
 
-                fn attempt_take() -> Option<u16> {
-                    mspcs::with(|cs| {
-                        IN_BUFFER
-                            .borrow(cs)
-                            .try_borrow_mut()
-                            // Staying in idle state and busy-waiting is reasonable behavior for
-                            // now if we couldn't borrow the IN_BUFFER.
-                            .map_or(None, |mut b| b.take())
-                    })
-                }
+// An XT keyboard on an AT host: answer the host's commands, and send it keys
+// in Set 2.
+#[cfg(feature = "xt-keyboard")]
+#[entry(interrupt_enable(pre_interrupt = init))]
+fn main() -> ! {
+    let mut device = Device::new();
 
-                loop {
-                    if let Some(b_in) = attempt_take() {
-                        let mut bits_in = b_in;
-                        bits_in &= !(0x4000 + 0x0001); // Mask out start/stop bit.
-                        bits_in >>= 2; // Remove stop bit and parity bit (FIXME: Check parity).
-                        break ProcReply::GrabbedKey((bits_in as u8).swap_bits());
-                    }
-                    // If host computer wants to reset
-                    if reset_requested() {
-                        send_byte_to_at_keyboard(Cmd::RESET).unwrap();
-                        send_byte_to_pc(Cmd::SELF_TEST_PASSED).unwrap();
-                        break ProcReply::KeyboardReset;
-                    }
+    send_to_pc(device.self_test()).unwrap();
+
+    loop {
+        match wait_for_input() {
+            Input::HostByte(b) => send_to_pc(device.command(b)).unwrap(),
+            Input::Key(k) => {
+                send_to_pc(device.key(k.byte)).unwrap();
+
+                if k.status.contains(Status::OVERRUN) {
+                    send_to_pc(device.overrun()).unwrap();
                 }
             }
+            Input::HostReset(_) => {}
         }
     }
 }
 
-pub fn send_xt_bit(bit: u8) -> Result<(), ()> {
-    mspcs::with(|cs| {
-        let port = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+enum Input {
+    Key(Keycode),
+    #[cfg(any(feature = "at-host", feature = "xt-keyboard"))]
+    HostByte(u8),
+    #[cfg(feature = "ps2-mouse")]
+    Mouse(Keycode),
+    #[cfg(feature = "ps2-mouse")]
+    MouseProbe,
+    #[cfg(feature = "second-keyboard")]
+    SecondKey(Keycode),
+    HostReset(XtPort),
+}
 
-        if bit == 1 {
-            driver::set(port, Pins::XT_DATA);
-        } else {
-            driver::unset(port, Pins::XT_DATA);
+fn wait_for_input() -> Input {
+    // The micro spends the majority of its life idle. It is possible for the host PC and
+    // the keyboard to send data to the micro at the same time. Host resets are caught
+    // (and answered) by the PORT1 ISR whenever they happen, but the rest of the reset
+    // only happens here, between keys.
+    fn reset_requested(xt: XtPort) -> bool {
+        host_reset(xt).load(Ordering::SeqCst)
+    }
+
+    #[cfg(any(feature = "at-host", feature = "xt-keyboard"))]
+    fn host_sent() -> bool {
+        !HOST_IN.is_empty()
+    }
+
+    #[cfg(not(any(feature = "at-host", feature = "xt-keyboard")))]
+    fn host_sent() -> bool {
+        false
+    }
+
+    #[cfg(feature = "ps2-mouse")]
+    fn aux_sent() -> bool {
+        !AUX_BUFFER.is_empty() || RTS_RAISED.load(Ordering::SeqCst)
+    }
+
+    #[cfg(feature = "second-keyboard")]
+    fn aux_sent() -> bool {
+        !AUX_BUFFER.is_empty() && pc_queue_len() <= XtBuffer::CAPACITY - XT_ROOM_NEEDED
+    }
+
+    #[cfg(not(any(feature = "ps2-mouse", feature = "second-keyboard")))]
+    fn aux_sent() -> bool {
+        false
+    }
+
+    fn pc_queue_len() -> u8 {
+        mspcs::with(|cs| {
+            out_buffer(active_host())
+                .borrow(cs)
+                .try_borrow()
+                .map_or(XT_HOLD, |b| b.len())
+        })
+    }
+
+    loop {
+        let queued = pc_queue_len();
+
+        // There's no holding off an XT keyboard.
+        #[cfg(not(feature = "xt-keyboard"))]
+        {
+            if queued >= XT_HOLD {
+                hold_keyboard(&XT_FULL, true);
+            } else if queued <= XT_RESUME {
+                hold_keyboard(&XT_FULL, false);
+            }
+
+            // The PORT1 ISR sets IN_FULL; only the main loop can clear it.
+            if IN_BUFFER.len() <= IN_RESUME {
+                hold_keyboard(&IN_FULL, false);
+            }
+
+            #[cfg(feature = "second-keyboard")]
+            if AUX_BUFFER.len() <= SECOND_RESUME {
+                hold_keyboard(&SECOND_FULL, false);
+            }
         }
 
-        driver::unset(port, Pins::XT_CLK);
+        // If host computer wants to reset; keys from before don't matter.
+        if let Some(xt) = XtPort::ALL
+            .iter()
+            .copied()
+            .find(|xt| host_reset(*xt).swap(false, Ordering::SeqCst))
+        {
+            break Input::HostReset(xt);
+        }
 
-        Ok(())
-    })?;
+        // Commands from an AT host go ahead of keys, like a real keyboard.
+        #[cfg(any(feature = "at-host", feature = "xt-keyboard"))]
+        if let Some(k) = HOST_IN.take() {
+            if k.is_valid() {
+                break Input::HostByte(k.byte);
+            }
 
-    delay_us!(55)?;
+            continue;
+        }
 
-    mspcs::with(|cs| {
-        let port = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+        if queued > XtBuffer::CAPACITY - XT_ROOM_NEEDED {
+            // Leave whatever is in IN_BUFFER there until the host catches up.
+        } else if let Some(k) = IN_BUFFER.take() {
+            break Input::Key(k);
+        }
 
-        driver::set(port, Pins::XT_CLK);
-        Ok(())
-    })?;
+        // The second keyboard's keys wait their turn behind the first's.
+        #[cfg(feature = "second-keyboard")]
+        if queued <= XtBuffer::CAPACITY - XT_ROOM_NEEDED {
+            if let Some(k) = AUX_BUFFER.take() {
+                break Input::SecondKey(k);
+            }
+        }
+
+        // Mouse packets go out on their own line, and can wait for keys.
+        #[cfg(feature = "ps2-mouse")]
+        if RTS_RAISED.swap(false, Ordering::SeqCst) {
+            break Input::MouseProbe;
+        }
+
+        #[cfg(feature = "ps2-mouse")]
+        if let Some(k) = AUX_BUFFER.take() {
+            break Input::Mouse(k);
+        }
+
+        // A full OUT_BUFFER wakes us up as it drains.
+        sleep_unless(|| {
+            XtPort::ALL.iter().any(|xt| reset_requested(*xt))
+                || host_sent()
+                || aux_sent()
+                || (!IN_BUFFER.is_empty() && pc_queue_len() <= XtBuffer::CAPACITY - XT_ROOM_NEEDED)
+        });
+    }
+}
+
+// Decodes a byte from one of the keyboards.
+#[cfg(not(feature = "xt-keyboard"))]
+fn handle_keycode(
+    k: Keycode,
+    kbd: Keyboard,
+    decoder: &mut Decoder,
+    encoder: &mut Encoder,
+    keys: &mut KeyState,
+) -> Result<(), ()> {
+    // A garbled frame could have been any byte at all.
+    let decoded = if k.is_valid() {
+        decoder.feed(k.byte)
+    } else {
+        decoder.reset();
+        Some(Decoded::Desync)
+    };
+
+    match decoded {
+        Some(Decoded::Key(ev)) => {
+            let ev = keymap::remap_keyboard(kbd, ev);
+
+            #[cfg(feature = "special-keys")]
+            match function_key(ev) {
+                Some(f) => run_function(f, ev, kbd, decoder, encoder, keys)?,
+                None => handle_key(ev, encoder, keys)?,
+            }
+            #[cfg(not(feature = "special-keys"))]
+            handle_key(ev, encoder, keys)?;
+        }
+        // The keyboard was plugged back in; it forgot both held keys and
+        // LEDs.
+        Some(Decoded::Attached) => {
+            keyboard_absent(kbd).store(false, Ordering::SeqCst);
+            keys.forget_keys();
+            release_keys(encoder)?;
+            toggle_leds(keys.locks())?;
+        }
+        // Whatever is in the buffer can't be trusted either.
+        Some(Decoded::Desync) => {
+            clear_buffer(kbd);
+            release_keys(encoder)?;
+        }
+        None => {}
+    }
+
+    // The BIOS beeps; keys may have been released in the gap.
+    if k.status.contains(Status::OVERRUN) {
+        send_byte_to_pc(Encoder::OVERRUN)?;
+        decoder.reset();
+        keys.forget_keys();
+        release_keys(encoder)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "xt-keyboard"))]
+fn handle_key(ev: KeyEvent, encoder: &mut Encoder, keys: &mut KeyState) -> Result<(), ()> {
+    if let Some(m) = keys.update(ev) {
+        toggle_leds(m)?;
+    }
+
+    #[cfg(feature = "layouts")]
+    let ev = keys.remap(ev);
+
+    #[cfg(not(feature = "ascii"))]
+    let bytes = encoder.encode(ev);
+    #[cfg(feature = "ascii")]
+    let bytes = encoder.encode(ev, keys.modifiers(), keys.locks());
+
+    send_to_pc(bytes)
+}
+
+// Keys that run a converter function instead of being sent on.
+#[cfg(all(feature = "special-keys", not(feature = "xt-keyboard")))]
+fn function_key(ev: KeyEvent) -> Option<Function> {
+    match keymap::special(ev.key) {
+        Some(Action::Function(f)) if ev.extended => Some(f),
+        _ => None,
+    }
+}
+
+#[cfg(all(feature = "special-keys", not(feature = "xt-keyboard")))]
+fn run_function(
+    f: Function,
+    ev: KeyEvent,
+    kbd: Keyboard,
+    decoder: &mut Decoder,
+    encoder: &mut Encoder,
+    keys: &mut KeyState,
+) -> Result<(), ()> {
+    // Function keys are all extended, which never toggles a lock.
+    keys.update(ev);
+
+    // Holding a function key down mustn't run it over and over, or e.g.
+    // flip back and forth between hosts.
+    if keys.repeated(ev) {
+        return Ok(());
+    }
+
+    match (f, ev.pressed) {
+        (Function::ResetKeyboard, true) => {
+            send_byte_to_keyboard(kbd, Cmd::RESET)?;
+            clear_buffer(kbd);
+            decoder.reset();
+            keys.forget_keys();
+            release_keys(encoder)
+        }
+        // The LEDs show the layout, not the lock state, until the
+        // layout key is released. Held keys are released first, or
+        // their breaks would be remapped to different keys.
+        #[cfg(feature = "layouts")]
+        (Function::NextLayout, true) => {
+            release_keys(encoder)?;
+            toggle_leds(keys.next_layout().leds())
+        }
+        #[cfg(feature = "layouts")]
+        (Function::NextLayout, false) => toggle_leds(keys.locks()),
+        #[cfg(feature = "kvm")]
+        (Function::SwitchHost, true) => switch_host(encoder, keys),
+        #[cfg(feature = "kvm")]
+        (Function::SwitchHost, false) => Ok(()),
+        (Function::ResetKeyboard, false) => Ok(()),
+    }
+}
+
+// Any keys still held on the host would otherwise stay stuck.
+#[cfg(not(feature = "xt-keyboard"))]
+fn release_keys(encoder: &mut Encoder) -> Result<(), ()> {
+    for b in encoder.release_all() {
+        send_byte_to_pc(b)?;
+    }
 
     Ok(())
 }
 
-pub fn send_byte_to_pc(mut byte: u8) -> Result<(), ()> {
-    fn wait_for_host() -> Result<bool, ()> {
+#[cfg(not(feature = "xt-keyboard"))]
+fn clear_buffer(kbd: Keyboard) {
+    match kbd {
+        Keyboard::First => IN_BUFFER.flush(),
+        #[cfg(feature = "second-keyboard")]
+        Keyboard::Second => AUX_BUFFER.flush(),
+    }
+}
+
+// Looks for the keyboard again in case it came back quietly, and starts over
+// with it.
+#[cfg(not(feature = "xt-keyboard"))]
+fn reset_keyboard(kbd: Keyboard, decoder: &mut Decoder) -> Result<(), ()> {
+    keyboard_absent(kbd).store(false, Ordering::SeqCst);
+    send_byte_to_keyboard(kbd, Cmd::RESET)?;
+    clear_buffer(kbd);
+    decoder.reset();
+    Ok(())
+}
+
+#[cfg(not(feature = "xt-keyboard"))]
+fn keyboard_absent(kbd: Keyboard) -> &'static AtomicBool {
+    match kbd {
+        Keyboard::First => &KEYBOARD_ABSENT,
+        #[cfg(feature = "second-keyboard")]
+        Keyboard::Second => &SECOND_ABSENT,
+    }
+}
+
+// The old host gets breaks for every key it thinks is held, and its locks
+// are kept for when we come back.
+#[cfg(feature = "kvm")]
+fn switch_host(encoder: &mut Encoder, keys: &mut KeyState) -> Result<(), ()> {
+    release_keys(encoder)?;
+
+    mspcs::with(|cs| {
+        let active = ACTIVE_HOST.borrow(cs);
+        active.set(active.get().other());
+    });
+
+    keys.switch_locks();
+    toggle_leds(keys.locks())
+}
+
+// The host keys go to.
+#[cfg(not(feature = "kvm"))]
+fn active_host() -> XtPort {
+    XtPort::First
+}
+
+#[cfg(feature = "kvm")]
+fn active_host() -> XtPort {
+    mspcs::with(|cs| ACTIVE_HOST.borrow(cs).get())
+}
+
+fn host_reset(xt: XtPort) -> &'static AtomicBool {
+    match xt {
+        XtPort::First => &HOST_RESET,
+        #[cfg(feature = "kvm")]
+        XtPort::Second => &SECOND_HOST_RESET,
+    }
+}
+
+fn host_absent(xt: XtPort) -> &'static AtomicBool {
+    match xt {
+        XtPort::First => &HOST_ABSENT,
+        #[cfg(feature = "kvm")]
+        XtPort::Second => &SECOND_HOST_ABSENT,
+    }
+}
+
+fn out_buffer(xt: XtPort) -> &'static Mutex<RefCell<XtBuffer>> {
+    match xt {
+        XtPort::First => &OUT_BUFFER,
+        #[cfg(feature = "kvm")]
+        XtPort::Second => &SECOND_OUT_BUFFER,
+    }
+}
+
+fn reset_pulse(xt: XtPort) -> &'static Mutex<Cell<Option<LowPulse>>> {
+    match xt {
+        XtPort::First => &XT_RESET_PULSE,
+        #[cfg(feature = "kvm")]
+        XtPort::Second => &SECOND_RESET_PULSE,
+    }
+}
+
+pub fn send_byte_to_pc(byte: u8) -> Result<(), ()> {
+    send_to_pc(XtBytes::one(byte))
+}
+
+fn send_to_pc(bytes: XtBytes) -> Result<(), ()> {
+    send_to_host(active_host(), bytes)
+}
+
+fn send_to_host(xt: XtPort, bytes: XtBytes) -> Result<(), ()> {
+    if host_absent(xt).load(Ordering::SeqCst) && matches!(HOST_ABSENT_POLICY, HostAbsent::Discard) {
+        return Ok(());
+    }
+
+    fn try_put(xt: XtPort, bytes: XtBytes) -> Result<bool, ()> {
         mspcs::with(|cs| {
-            let port = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+            let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).ok_or(())?;
 
-            let clk_or_data_unset =
-                driver::is_unset(port, Pins::XT_CLK) || driver::is_unset(port, Pins::XT_DATA);
+            let queued = out_buffer(xt)
+                .borrow(cs)
+                .try_borrow_mut()
+                .is_ok_and(|mut b| b.put_all(bytes).is_ok());
 
-            if !clk_or_data_unset {
-                driver::xt_out(port);
+            if queued {
+                start_xt_tx(timer);
             }
 
-            Ok(clk_or_data_unset)
+            Ok(queued)
         })
     }
 
-    // The host cannot send data; the only communication it can do with the micro is pull
-    // the CLK (reset) and DATA (shift register full) low.
-    // Wait for the host to release the lines.
-    while wait_for_host()? {}
+    // Wait for room if the host is slow.
+    while !try_put(xt, bytes.clone())? {
+        sleep_unless(|| !XT_BUSY.load(Ordering::SeqCst));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "xt-keyboard"))]
+fn keyboard_held() -> bool {
+    XT_FULL.load(Ordering::SeqCst) || IN_FULL.load(Ordering::SeqCst)
+}
 
-    send_xt_bit(0)?;
-    send_xt_bit(1)?;
+#[cfg(feature = "second-keyboard")]
+fn aux_held() -> bool {
+    XT_FULL.load(Ordering::SeqCst) || SECOND_FULL.load(Ordering::SeqCst)
+}
 
-    for _ in 0..8 {
-        send_xt_bit(byte & 0x01)?; /* Send data... */
-        byte >>= 1;
+// The mouse is never held off.
+#[cfg(feature = "ps2-mouse")]
+fn aux_held() -> bool {
+    false
+}
+
+// Holding the AT clock low makes the keyboard buffer keys instead of sending
+// them. Frames already in progress are finished by the PORT1 ISR first.
+#[cfg(not(feature = "xt-keyboard"))]
+fn hold_keyboard(reason: &AtomicBool, hold: bool) {
+    if reason.load(Ordering::SeqCst) == hold {
+        return;
     }
 
     mspcs::with(|cs| {
-        let port = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+        let port = At2XtPeripherals::periph_ref(cs).unwrap();
 
-        driver::xt_in(port);
-        Ok(())
-    })?;
+        reason.store(hold, Ordering::SeqCst);
 
-    Ok(())
+        if HOST_MODE.load(Ordering::SeqCst) || !KEY_IN.borrow(cs).get().is_empty() {
+            // The PORT1 ISR or send_byte_to_at_keyboard will see the change.
+        } else if keyboard_held() {
+            // A start bit that slipped in before the clock went low will be
+            // sent again by the keyboard along with the rest of its frame.
+            driver::at_inhibit(port);
+            let mut keyin = KEY_IN.borrow(cs).get();
+            keyin.clear();
+            KEY_IN.borrow(cs).set(keyin);
+            driver::clear_at_clk_int(port);
+        } else {
+            driver::at_idle(port);
+        }
+
+        #[cfg(feature = "second-keyboard")]
+        if AUX_HOST_MODE.load(Ordering::SeqCst) || !AUX_IN.borrow(cs).get().is_empty() {
+            // aux_clk_edge or send_byte_to_aux will see the change.
+        } else if aux_held() {
+            driver::aux_inhibit(port);
+            let mut aux_in = AUX_IN.borrow(cs).get();
+            aux_in.clear();
+            AUX_IN.borrow(cs).set(aux_in);
+            driver::clear_aux_clk_int(port);
+        } else {
+            driver::aux_idle(port);
+        }
+    });
 }
 
+// Kicks the transmitter if it went idle.
+fn start_xt_tx(timer: &msp430g2211::TIMER_A2) {
+    if !XT_BUSY.load(Ordering::SeqCst) {
+        XT_BUSY.store(true, Ordering::SeqCst);
+        let now = timer.tar.read().bits();
+        timer.taccr1.write(|w| w.taccr1().bits(now.wrapping_add(1)));
+        timer.tacctl1.write(|w| w.ccie().set_bit());
+    }
+}
+
+#[cfg(not(feature = "xt-keyboard"))]
+fn send_byte_to_keyboard(kbd: Keyboard, byte: u8) -> Result<(), ()> {
+    match kbd {
+        Keyboard::First => send_byte_to_at_keyboard(byte),
+        #[cfg(feature = "second-keyboard")]
+        Keyboard::Second => send_byte_to_second_keyboard(byte),
+    }
+}
+
+#[cfg(not(feature = "xt-keyboard"))]
 fn send_byte_to_at_keyboard(byte: u8) -> Result<(), ()> {
-    // TODO: What does the AT keyboard protocol say about retrying xfers
-    // when inhibiting communication? Does the keyboard retry from the beginning
-    // or from the interrupted bit? Right now, we don't flush KeyIn, so
-    // we do it from the interrupted bit. This seems to work fine.
-    fn wait_for_at_keyboard() -> Result<bool, ()> {
+    // Nobody to talk to; the keyboard announces itself with 0xAA when it is
+    // plugged in.
+    if KEYBOARD_ABSENT.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    // A keyboard that misses a bit or never clocks the byte in has aborted
+    // the transfer; start over from the request to send.
+    for _ in 0..AT_TX_TRIES {
+        if try_send_to_at_keyboard(byte)? {
+            return Ok(());
+        }
+    }
+
+    KEYBOARD_ABSENT.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+#[cfg(not(feature = "xt-keyboard"))]
+fn try_send_to_at_keyboard(byte: u8) -> Result<bool, ()> {
+    // A keyboard inhibited before the 11th clock abandons the frame and sends
+    // it again from the start bit later, so a partial frame is thrown away.
+    // One whose stop bit is due any moment is left to finish, so we never
+    // guess which side of the 11th clock the keyboard saw.
+    fn claim_at_lines(byte: u8) -> Result<bool, ()> {
         mspcs::with(|cs| {
             let port = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+            let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+            let now = timer.tar.read().bits();
+            let mut keyin = KEY_IN.borrow(cs).get();
 
-            let unset = driver::is_unset(port, Pins::AT_CLK);
-
-            if !unset {
-                driver::at_inhibit(port);
+            if driver::is_unset(port, Pins::AT_CLK) || keyin.is_finishing(now) {
+                return Ok(false);
             }
 
-            Ok(unset)
+            driver::at_inhibit(port);
+            driver::disable_at_clk_int(port);
+            keyin.clear();
+            KEY_IN.borrow(cs).set(keyin);
+
+            let mut key_out = KEY_OUT.borrow(cs).get();
+
+            key_out.put(byte, now)?;
+
+            // Safe outside of critical section: As long as HOST_MODE is
+            // not set, it's not possible for the interrupt
+            // context to touch this variable.
+            KEY_OUT.borrow(cs).set(key_out);
+            Ok(true)
         })
     }
 
     mspcs::with(|cs| {
         let port = At2XtPeripherals::periph_ref(cs).ok_or(())?;
 
-        let mut key_out = KEY_OUT.borrow(cs).get();
-
-        key_out.put(byte)?;
-
-        // Safe outside of critical section: As long as HOST_MODE is
-        // not set, it's not possible for the interrupt
-        // context to touch this variable.
-        KEY_OUT.borrow(cs).set(key_out);
-        driver::disable_at_clk_int(port);
+        // A held clock would look like the keyboard talking to us forever.
+        if keyboard_held() {
+            driver::at_idle(port);
+        }
         Ok(())
     })?;
 
-    /* If/when timer int is enabled, this loop really needs to allow preemption during
-    I/O read. Can it be done without overhead of CriticalSection? */
-    while wait_for_at_keyboard()? {}
+    // With no keyboard, AT_CLK may never go high.
+    start_timer(AT_TX_TIMEOUT)?;
+    while !claim_at_lines(byte)? {
+        if TIMEOUT.load(Ordering::SeqCst) {
+            mspcs::with(|cs| {
+                let port = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+
+                if keyboard_held() {
+                    driver::at_inhibit(port);
+                }
+                Ok(())
+            })?;
+
+            return Ok(false);
+        }
+    }
 
     delay_us!(100)?;
 
@@ -373,33 +1587,325 @@ fn send_byte_to_at_keyboard(byte: u8) -> Result<(), ()> {
         Ok(())
     })?;
 
-    while !DEVICE_ACK.load(Ordering::SeqCst) {}
+    // The keyboard has 15ms to start clocking and 2ms to finish.
+    start_timer(AT_TX_TIMEOUT)?;
+    while !DEVICE_ACK.load(Ordering::SeqCst) && !TIMEOUT.load(Ordering::SeqCst) {
+        sleep_unless(|| DEVICE_ACK.load(Ordering::SeqCst) || TIMEOUT.load(Ordering::SeqCst));
+    }
+
+    mspcs::with(|cs| {
+        let port = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+
+        HOST_MODE.store(false, Ordering::SeqCst);
+
+        let acked = DEVICE_ACK.load(Ordering::SeqCst);
+
+        if !acked {
+            let mut key_out = KEY_OUT.borrow(cs).get();
+            key_out.clear();
+            KEY_OUT.borrow(cs).set(key_out);
+            driver::at_idle(port);
+        }
+
+        if keyboard_held() {
+            driver::at_inhibit(port);
+        }
+        Ok(acked)
+    })
+}
+
+// Sends a byte to the mouse, noting it if the mouse took it. A mouse that
+// isn't there, or drops the byte, is set up again when it next passes its
+// self test.
+#[cfg(feature = "ps2-mouse")]
+fn command_mouse(mouse: &mut Mouse, byte: u8) -> Result<(), ()> {
+    if send_byte_to_aux(byte)? {
+        mouse.sent(byte);
+    }
+
+    Ok(())
+}
+
+// Given up on the same way as the first keyboard.
+#[cfg(feature = "second-keyboard")]
+fn send_byte_to_second_keyboard(byte: u8) -> Result<(), ()> {
+    if SECOND_ABSENT.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    for _ in 0..AT_TX_TRIES {
+        if send_byte_to_aux(byte)? {
+            return Ok(());
+        }
+    }
+
+    SECOND_ABSENT.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+// Like try_send_to_at_keyboard, except a partial frame is always thrown away.
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+fn send_byte_to_aux(byte: u8) -> Result<bool, ()> {
+    mspcs::with(|cs| {
+        let port = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+        let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+        let now = timer.tar.read().bits();
+
+        driver::aux_inhibit(port);
+        driver::disable_aux_clk_int(port);
+
+        let mut aux_in = AUX_IN.borrow(cs).get();
+        aux_in.clear();
+        AUX_IN.borrow(cs).set(aux_in);
+
+        let mut aux_out = AUX_OUT.borrow(cs).get();
+        aux_out.put(byte, now)?;
+        AUX_OUT.borrow(cs).set(aux_out);
+        Ok(())
+    })?;
+
+    delay_us!(100)?;
+
+    mspcs::with(|cs| {
+        let port = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+
+        driver::unset(port, driver::AUX_DATA);
+        Ok(())
+    })?;
+
+    delay_us!(33)?;
+
+    mspcs::with(|cs| {
+        let port = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+
+        driver::set(port, driver::AUX_CLK);
+        driver::mk_in(port, driver::AUX_CLK);
+        driver::clear_aux_clk_int(port);
+
+        driver::enable_aux_clk_int(port);
+        AUX_HOST_MODE.store(true, Ordering::SeqCst);
+        AUX_ACK.store(false, Ordering::SeqCst);
+        Ok(())
+    })?;
+
+    start_timer(AT_TX_TIMEOUT)?;
+    while !AUX_ACK.load(Ordering::SeqCst) && !TIMEOUT.load(Ordering::SeqCst) {
+        sleep_unless(|| AUX_ACK.load(Ordering::SeqCst) || TIMEOUT.load(Ordering::SeqCst));
+    }
+
+    mspcs::with(|cs| {
+        let port = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+
+        AUX_HOST_MODE.store(false, Ordering::SeqCst);
+
+        let acked = AUX_ACK.load(Ordering::SeqCst);
+
+        if !acked {
+            let mut aux_out = AUX_OUT.borrow(cs).get();
+            aux_out.clear();
+            AUX_OUT.borrow(cs).set(aux_out);
+            driver::aux_idle(port);
+        }
+
+        if aux_held() {
+            driver::aux_inhibit(port);
+        }
+        Ok(acked)
+    })
+}
+
+#[cfg(feature = "ps2-mouse")]
+fn handle_mouse(k: Keycode, mouse: &mut Mouse) -> Result<(), ()> {
+    if !k.is_valid() {
+        mouse.resync();
+        return Ok(());
+    }
+
+    match mouse.feed(k.byte) {
+        // Each packet holds up the main loop for 22ms at 1200 baud; 20 a
+        // second leaves it more than half its time for keys, even while the
+        // mouse is moving.
+        Some(MouseEvent::Attached) => {
+            command_mouse(mouse, Mouse::SET_SAMPLE_RATE)?;
+            command_mouse(mouse, MOUSE_SAMPLE_RATE)?;
+            command_mouse(mouse, Mouse::ENABLE)?;
+            send_to_serial_mouse(&[mouse::SERIAL_ID])?;
+        }
+        Some(MouseEvent::Packet(p)) => send_to_serial_mouse(&mouse::to_serial(p))?,
+        None => {}
+    }
+
+    // Bytes were dropped after this one, so the next few are mid-packet.
+    if k.status.contains(Status::OVERRUN) {
+        mouse.resync();
+    }
+
+    Ok(())
+}
+
+// Bit-bangs 7N1 on MOUSE_TX. TIMER_A2's other channel belongs to the XT
+// transmitter, so edges are timed from here against their absolute
+// deadlines; a late wakeup can't stretch the byte.
+#[cfg(feature = "ps2-mouse")]
+fn send_to_serial_mouse(bytes: &[u8]) -> Result<(), ()> {
+    const OUT: UartOut = UartOut::new(mouse::SERIAL_BAUD, 7);
+
+    let mut out = OUT;
+    let mut at = mspcs::with(|cs| {
+        let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+        Ok(timer.tar.read().bits())
+    })?;
+
+    for b in bytes {
+        out.put(*b)?;
 
-    HOST_MODE.store(false, Ordering::SeqCst);
+        while let Some((level, ticks)) = out.step() {
+            mspcs::with(|cs| {
+                let port = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+
+                if level {
+                    driver::set(port, Pins::MOUSE_TX);
+                } else {
+                    driver::unset(port, Pins::MOUSE_TX);
+                }
+                Ok(())
+            })?;
+
+            at = at.wrapping_add(ticks);
+            start_timer_at(at)?;
+            while !TIMEOUT.load(Ordering::SeqCst) {
+                sleep_unless(|| TIMEOUT.load(Ordering::SeqCst));
+            }
+        }
+    }
 
     Ok(())
 }
 
+// Both keyboards show the same locks.
+#[cfg(not(feature = "xt-keyboard"))]
 fn toggle_leds(mask: LedMask) -> Result<(), ()> {
-    send_byte_to_at_keyboard(Cmd::SET_LEDS)?;
+    set_leds(Keyboard::First, mask)?;
+    #[cfg(feature = "second-keyboard")]
+    set_leds(Keyboard::Second, mask)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "xt-keyboard"))]
+fn set_leds(kbd: Keyboard, mask: LedMask) -> Result<(), ()> {
+    send_byte_to_keyboard(kbd, Cmd::SET_LEDS)?;
     delay_us!(3000)?;
-    send_byte_to_at_keyboard(mask.bits())?;
+    send_byte_to_keyboard(kbd, mask.bits())?;
     Ok(())
 }
 
+#[cfg(not(feature = "xt-keyboard"))]
 fn delay(time: u16) -> Result<(), ()> {
     start_timer(time)?;
-    while !TIMEOUT.load(Ordering::SeqCst) {}
+    while !TIMEOUT.load(Ordering::SeqCst) {
+        sleep_unless(|| TIMEOUT.load(Ordering::SeqCst));
+    }
 
     Ok(())
 }
 
+// Sleeps until the next interrupt, unless ready() says the thing we are
+// waiting for already happened. ready() runs with interrupts off, and
+// going to sleep turns them back on in the same instruction, so an interrupt
+// can't slip in between and leave us asleep with work to do. Every ISR wakes
+// the CPU on exit.
+//
+// LPM3 stops SMCLK and thus TIMER_A2, so it is only used when nothing is
+// being timed: no delay, no XT byte going out, no host reset pulse, and no AT
+// frame in progress (its bit timestamps would go stale without the timer
+// noticing).
+fn sleep_unless<F: FnOnce() -> bool>(ready: F) {
+    msp430::interrupt::disable();
+
+    let lpm = mspcs::with(|cs| {
+        let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).unwrap();
+
+        if ready() {
+            None
+        } else if XT_BUSY.load(Ordering::SeqCst)
+            || HOST_MODE.load(Ordering::SeqCst)
+            || XtPort::ALL
+                .iter()
+                .any(|xt| reset_pulse(*xt).borrow(cs).get().is_some())
+            || timer.tacctl0.read().ccie().bit_is_set()
+            || !KEY_IN.borrow(cs).get().is_empty()
+            || xt_keyboard_busy(cs)
+            || aux_busy(cs)
+        {
+            Some(Lpm::Lpm0)
+        } else {
+            Some(Lpm::Lpm3)
+        }
+    });
+
+    lpm::enable_interrupts(lpm);
+}
+
+// The XT keyboard's bits are timed against TIMER_A2, which LPM3 stops.
+#[cfg(feature = "xt-keyboard")]
+fn xt_keyboard_busy(cs: CriticalSection) -> bool {
+    !XT_IN.borrow(cs).get().is_empty()
+}
+
+#[cfg(not(feature = "xt-keyboard"))]
+fn xt_keyboard_busy(_cs: CriticalSection) -> bool {
+    false
+}
+
+// Same for frames on the aux port.
+#[cfg(any(feature = "ps2-mouse", feature = "second-keyboard"))]
+fn aux_busy(cs: CriticalSection) -> bool {
+    AUX_HOST_MODE.load(Ordering::SeqCst) || !AUX_IN.borrow(cs).get().is_empty()
+}
+
+#[cfg(not(any(feature = "ps2-mouse", feature = "second-keyboard")))]
+fn aux_busy(_cs: CriticalSection) -> bool {
+    false
+}
+
+// Like start_timer, but for a deadline rather than a length of time. One
+// already past times out at once.
+#[cfg(feature = "ps2-mouse")]
+fn start_timer_at(at: u16) -> Result<(), ()> {
+    let late = mspcs::with(|cs| {
+        let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+        let left = at.wrapping_sub(timer.tar.read().bits());
+
+        // Deadlines are never more than a bit away.
+        Ok(left == 0 || left > u16::MAX / 2)
+    })?;
+
+    if late {
+        TIMEOUT.store(true, Ordering::SeqCst);
+        Ok(())
+    } else {
+        mspcs::with(|cs| {
+            let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).ok_or(())?;
+            timer.taccr0.write(|w| w.taccr0().bits(at));
+            TIMEOUT.store(false, Ordering::SeqCst);
+            timer.tacctl0.write(|w| w.ccie().set_bit());
+            Ok(())
+        })
+    }
+}
+
+#[cfg(not(feature = "xt-keyboard"))]
 fn start_timer(time: u16) -> Result<(), ()> {
     mspcs::with(|cs| {
         let timer: &msp430g2211::TIMER_A2 = At2XtPeripherals::periph_ref(cs).ok_or(())?;
 
         TIMEOUT.store(false, Ordering::SeqCst);
-        timer.taccr0.write(|w| w.taccr0().bits(time));
+        let now = timer.tar.read().bits();
+        timer
+            .taccr0
+            .write(|w| w.taccr0().bits(now.wrapping_add(time)));
+        // Writing the control register also clears any stale CCIFG.
+        timer.tacctl0.write(|w| w.ccie().set_bit());
         Ok(())
     })
 }
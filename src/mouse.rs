@@ -0,0 +1,256 @@
+use core::convert::TryFrom;
+
+// What a Microsoft serial mouse sends, and how fast.
+pub const SERIAL_BAUD: u32 = 1200;
+pub const SERIAL_ID: u8 = b'M';
+
+pub enum MouseEvent {
+    Attached, // Passed its self test; it needs setting up again.
+    Packet([u8; 3]),
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Disabled,   // Not reporting yet; only a self test means anything.
+    Packet(u8), // Bytes of the current packet received.
+    SelfTest,   // Got 0xAA; a 0x00 ID would make it a self test.
+}
+
+// Assembles the 3-byte packets of a PS/2 mouse in stream mode.
+pub struct Mouse {
+    state: State,
+    packet: [u8; 3],
+    acks_due: u8,   // For bytes sent to the mouse.
+    enabling: bool, // Packets follow the last ACK.
+    buttons: u8,    // Left and right, as of the last packet passed on.
+}
+
+impl Mouse {
+    pub const SET_SAMPLE_RATE: u8 = 0xf3;
+    pub const ENABLE: u8 = 0xf4;
+    const ACK: u8 = 0xfa;
+    const SELF_TEST_PASSED: u8 = 0xaa;
+    const ID: u8 = 0x00;
+    const ALWAYS_SET: u8 = 0x08; // In the first byte of every packet.
+    const BUTTONS: u8 = 0x03; // The ones a serial mouse has.
+    const SIGNS: u8 = 0xf0; // And overflows; a movement all the same.
+
+    pub const fn new() -> Mouse {
+        Mouse {
+            state: State::Disabled,
+            packet: [0; 3],
+            acks_due: 0,
+            enabling: false,
+            buttons: 0,
+        }
+    }
+
+    // The mouse took a byte from us; it will answer with an ACK.
+    pub fn sent(&mut self, byte: u8) {
+        self.acks_due = self.acks_due.saturating_add(1);
+
+        if byte == Self::ENABLE {
+            self.enabling = true;
+        }
+    }
+
+    // A garbled or dropped byte; wait for the start of the next packet.
+    pub fn resync(&mut self) {
+        if let State::Packet(_) | State::SelfTest = self.state {
+            self.state = State::Packet(0);
+        }
+    }
+
+    pub fn feed(&mut self, byte: u8) -> Option<MouseEvent> {
+        let between_packets = matches!(self.state, State::Disabled | State::Packet(0));
+
+        if byte == Self::ACK && self.acks_due > 0 && between_packets {
+            self.acks_due -= 1;
+
+            if self.acks_due == 0 && self.enabling {
+                self.enabling = false;
+                self.state = State::Packet(0);
+            }
+
+            return None;
+        }
+
+        let (state, event) = match self.state {
+            State::Disabled | State::Packet(0) if byte == Self::SELF_TEST_PASSED => {
+                self.packet = [byte, 0, 0];
+                (State::SelfTest, None)
+            }
+            State::Disabled => (State::Disabled, None),
+            State::SelfTest if byte == Self::ID => {
+                self.acks_due = 0;
+                self.enabling = false;
+                self.buttons = 0;
+                (State::Disabled, Some(MouseEvent::Attached))
+            }
+            // It was a packet after all.
+            State::SelfTest => self.packet_byte(1, byte),
+            State::Packet(0) if byte & Self::ALWAYS_SET == 0 => (State::Packet(0), None),
+            State::Packet(pos) => self.packet_byte(pos, byte),
+        };
+
+        self.state = state;
+        event
+    }
+
+    fn packet_byte(&mut self, pos: u8, byte: u8) -> (State, Option<MouseEvent>) {
+        if let Some(b) = self.packet.get_mut(usize::from(pos)) {
+            *b = byte;
+        }
+
+        if pos >= 2 {
+            (State::Packet(0), self.news().map(MouseEvent::Packet))
+        } else {
+            (State::Packet(pos + 1), None)
+        }
+    }
+
+    // Every bit sent costs the main loop time, so a packet that neither moves
+    // nor changes a button the serial mouse has is dropped.
+    fn news(&mut self) -> Option<[u8; 3]> {
+        let [flags, x, y] = self.packet;
+        let buttons = flags & Self::BUTTONS;
+        let moved = x != 0 || y != 0 || flags & Self::SIGNS != 0;
+
+        if moved || buttons != self.buttons {
+            self.buttons = buttons;
+            Some(self.packet)
+        } else {
+            None
+        }
+    }
+}
+
+// A PS/2 packet as a Microsoft serial mouse would send it: 7-bit bytes, the
+// first one marked by bit 6, holding the buttons and the top two bits of
+// each movement. Y counts up on PS/2 and down on the serial mouse. The
+// middle button has no place to go.
+pub fn to_serial(packet: [u8; 3]) -> [u8; 3] {
+    let [flags, x, y] = packet;
+
+    let x = movement(x, flags & 0x10 != 0, flags & 0x40 != 0);
+    let y = -movement(y, flags & 0x20 != 0, flags & 0x80 != 0);
+    let [x] = clamp(x).to_le_bytes();
+    let [y] = clamp(y).to_le_bytes();
+
+    let left = (flags & 0x01) << 5;
+    let right = (flags & 0x02) << 3;
+
+    [
+        0x40 | left | right | ((y >> 4) & 0x0c) | ((x >> 6) & 0x03),
+        x & 0x3f,
+        y & 0x3f,
+    ]
+}
+
+// PS/2 movements are 9-bit two's complement, with the sign bit in the first
+// byte. On overflow, the mouse moved as far as it can say.
+fn movement(low: u8, negative: bool, overflow: bool) -> i16 {
+    match (overflow, negative) {
+        (true, false) => 255,
+        (true, true) => -256,
+        (false, false) => i16::from(low),
+        (false, true) => i16::from(low) - 256,
+    }
+}
+
+fn clamp(m: i16) -> i8 {
+    i8::try_from(m).unwrap_or(if m < 0 { i8::MIN } else { i8::MAX })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> Mouse {
+        let mut mouse = Mouse::new();
+
+        mouse.sent(Mouse::ENABLE);
+        assert!(mouse.feed(0xfa).is_none());
+        mouse
+    }
+
+    fn packets(mouse: &mut Mouse, bytes: &[u8]) -> Vec<[u8; 3]> {
+        bytes
+            .iter()
+            .filter_map(|&b| match mouse.feed(b) {
+                Some(MouseEvent::Packet(p)) => Some(p),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn buttons_map_to_serial_bits() {
+        assert_eq!(to_serial([0x09, 0, 0]), [0x60, 0, 0]);
+        assert_eq!(to_serial([0x0a, 0, 0]), [0x50, 0, 0]);
+        // No middle button on a serial mouse.
+        assert_eq!(to_serial([0x0c, 0, 0]), [0x40, 0, 0]);
+    }
+
+    #[test]
+    fn y_counts_down_on_a_serial_mouse() {
+        // One right and one up on PS/2 is one right and minus one down.
+        assert_eq!(to_serial([0x08, 0x01, 0x01]), [0x4c, 0x01, 0x3f]);
+        assert_eq!(to_serial([0x28, 0x00, 0xff]), [0x40, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn big_movements_are_clamped() {
+        assert_eq!(to_serial([0x08, 0xc8, 0]), [0x41, 0x3f, 0]);
+        assert_eq!(to_serial([0x18, 0x38, 0]), [0x42, 0x00, 0]);
+        // Overflow wins over whatever the low bits say.
+        assert_eq!(to_serial([0x58, 0x7f, 0]), [0x42, 0x00, 0]);
+        assert_eq!(to_serial([0x48, 0x00, 0]), [0x41, 0x3f, 0]);
+    }
+
+    #[test]
+    fn packets_wait_for_enable() {
+        let mut mouse = Mouse::new();
+
+        assert!(packets(&mut mouse, &[0x09, 0x01, 0x01]).is_empty());
+    }
+
+    #[test]
+    fn packets_with_nothing_new_are_dropped() {
+        let mut mouse = enabled();
+
+        let sent = packets(
+            &mut mouse,
+            &[
+                0x08, 0, 0, // Nothing.
+                0x09, 0, 0, // Left down.
+                0x09, 0, 0, // Still down.
+                0x0d, 0, 0, // Middle down too.
+                0x09, 0x02, 0, // Moved.
+                0x08, 0, 0, // Left up.
+            ],
+        );
+        assert_eq!(sent, [[0x09, 0, 0], [0x09, 0x02, 0], [0x08, 0, 0]]);
+    }
+
+    #[test]
+    fn resync_waits_for_a_first_byte() {
+        let mut mouse = enabled();
+
+        assert!(packets(&mut mouse, &[0x09, 0x01]).is_empty());
+        mouse.resync();
+
+        // Bytes without bit 3 can't start a packet.
+        let sent = packets(&mut mouse, &[0x00, 0x01, 0x08, 0x01, 0x00]);
+        assert_eq!(sent, [[0x08, 0x01, 0x00]]);
+    }
+
+    #[test]
+    fn self_test_means_attached() {
+        let mut mouse = enabled();
+
+        assert!(mouse.feed(0xaa).is_none());
+        assert!(matches!(mouse.feed(0x00), Some(MouseEvent::Attached)));
+        assert!(packets(&mut mouse, &[0x09, 0x01, 0x01]).is_empty());
+    }
+}
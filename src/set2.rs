@@ -0,0 +1,453 @@
+use crate::keystate::KeyEvent;
+#[cfg(feature = "xt-keyboard")]
+use crate::{keymap, xt::XtBytes};
+
+// Commands the converter sends to the keyboard.
+pub struct Cmd;
+
+impl Cmd {
+    pub const SET_LEDS: u8 = 0xed;
+    #[allow(dead_code)]
+    pub const ECHO: u8 = 0xee;
+    #[allow(dead_code)]
+    pub const SCAN_CODE_SET: u8 = 0xf0;
+    #[allow(dead_code)]
+    pub const IDENTIFY: u8 = 0xf2;
+    #[allow(dead_code)]
+    pub const SET_TYPEMATIC: u8 = 0xf3;
+    #[allow(dead_code)]
+    pub const ENABLE: u8 = 0xf4;
+    #[allow(dead_code)]
+    pub const DISABLE: u8 = 0xf5;
+    #[allow(dead_code)]
+    pub const SET_DEFAULT: u8 = 0xf6;
+    #[allow(dead_code)]
+    pub const RESEND: u8 = 0xfe;
+    pub const RESET: u8 = 0xff;
+}
+
+pub enum Decoded {
+    Key(KeyEvent),
+    Attached, // Keyboard passed its self test without being asked.
+    Desync,   // Got a byte that makes no sense here; key state is lost.
+}
+
+enum State {
+    Idle,
+    Break,
+    Extended,
+    ExtendedBreak,
+    Pause(u8), // Position in PAUSE_SEQ.
+}
+
+pub struct Decoder {
+    state: State,
+}
+
+impl Decoder {
+    #[allow(dead_code)]
+    const ERROR1: u8 = 0x00;
+    const LAST_KEY: u8 = 0x84;
+    const SELF_TEST_PASSED: u8 = 0xaa;
+    const PREFIX: u8 = 0xe0;
+    const PREFIX_PAUSE: u8 = 0xe1;
+    const ECHO: u8 = 0xee;
+    const BREAK: u8 = 0xf0;
+    const ACK: u8 = 0xfa;
+    #[allow(dead_code)]
+    const SELF_TEST_FAILED1: u8 = 0xfc;
+    #[allow(dead_code)]
+    const SELF_TEST_FAILED2: u8 = 0xfd;
+    const NAK: u8 = 0xfe;
+    #[allow(dead_code)]
+    const ERROR2: u8 = 0xff;
+
+    // Pause has no break code; it sends its make and break all at once.
+    const PAUSE_SEQ: [u8; 8] = [0xe1, 0x14, 0x77, 0xe1, 0xf0, 0x14, 0xf0, 0x77];
+
+    pub const fn new() -> Decoder {
+        Decoder { state: State::Idle }
+    }
+
+    pub fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+
+    pub fn feed(&mut self, byte: u8) -> Option<Decoded> {
+        let is_key = byte != 0 && byte <= Self::LAST_KEY;
+
+        let (next_state, decoded) = match self.state {
+            State::Idle => match byte {
+                // TODO: 0xfa, 0xfe, and 0xee should never be sent unprompted.
+                Self::ACK | Self::NAK | Self::ECHO => (State::Idle, None),
+                Self::SELF_TEST_PASSED => (State::Idle, Some(Decoded::Attached)),
+                Self::BREAK => (State::Break, None),
+                Self::PREFIX => (State::Extended, None),
+                Self::PREFIX_PAUSE => (State::Pause(1), None),
+                _ if is_key => (State::Idle, Some(Self::key(byte, true, false))),
+                _ => (State::Idle, None),
+            },
+            State::Extended => match byte {
+                Self::BREAK => (State::ExtendedBreak, None),
+                _ if is_key => (State::Idle, Some(Self::key(byte, true, true))),
+                _ => (State::Idle, Some(Decoded::Desync)),
+            },
+            State::Break | State::ExtendedBreak if is_key => {
+                let extended = matches!(self.state, State::ExtendedBreak);
+                (State::Idle, Some(Self::key(byte, false, extended)))
+            }
+            State::Pause(pos) => {
+                let expected = Self::PAUSE_SEQ.get(usize::from(pos)).copied();
+
+                match (expected == Some(byte), pos) {
+                    (false, _) => (State::Idle, Some(Decoded::Desync)),
+                    (true, 2) => (
+                        State::Pause(3),
+                        Some(Self::key(KeyEvent::PAUSE, true, true)),
+                    ),
+                    (true, 7) => (State::Idle, Some(Self::key(KeyEvent::PAUSE, false, true))),
+                    (true, _) => (State::Pause(pos + 1), None),
+                }
+            }
+            State::Break | State::ExtendedBreak => (State::Idle, Some(Decoded::Desync)),
+        };
+
+        self.state = next_state;
+        decoded
+    }
+
+    fn key(key: u8, pressed: bool, extended: bool) -> Decoded {
+        Decoded::Key(KeyEvent {
+            key,
+            pressed,
+            extended,
+        })
+    }
+}
+
+// Plays an AT keyboard to the host, for an XT keyboard. Answers the host's
+// commands and turns Set 1 codes into Set 2 ones. An XT keyboard has no LEDs
+// and repeats keys on its own, so LED and typematic settings are ACKed and
+// dropped.
+#[cfg(feature = "xt-keyboard")]
+pub struct Device {
+    arg_for: Option<u8>, // Command still waiting for its argument.
+    enabled: bool,
+    lshift: bool, // Left Shift is down on the XT keyboard.
+    last: u8,     // Sent again on RESEND.
+}
+
+#[cfg(feature = "xt-keyboard")]
+impl Device {
+    const OVERRUN: u8 = 0x00;
+    const SELF_TEST_PASSED: u8 = 0xaa;
+    const ID: [u8; 2] = [0xab, 0x83];
+    const ECHO: u8 = 0xee;
+    const BREAK: u8 = 0xf0;
+    const ACK: u8 = 0xfa;
+    const RESEND: u8 = 0xfe;
+    const SCAN_CODE_SET: u8 = 2;
+
+    // Most bytes a single key or command gets back: IDENTIFY's reply.
+    pub const LONGEST: u8 = 3;
+
+    const XT_LSHIFT: u8 = 0x2a;
+    const XT_BREAK: u8 = 0x80;
+    const XT_OVERRUN: u8 = 0xff;
+
+    pub const fn new() -> Device {
+        Device {
+            arg_for: None,
+            enabled: true,
+            lshift: false,
+            last: Self::SELF_TEST_PASSED,
+        }
+    }
+
+    pub fn self_test(&mut self) -> XtBytes {
+        self.reply(XtBytes::one(Self::SELF_TEST_PASSED))
+    }
+
+    pub fn overrun(&mut self) -> XtBytes {
+        self.reply(XtBytes::one(Self::OVERRUN))
+    }
+
+    // Commands all have the top bit set, and their arguments never do; a
+    // command in place of an argument cancels the one before it.
+    pub fn command(&mut self, byte: u8) -> XtBytes {
+        let is_arg = byte & 0x80 == 0;
+
+        let bytes = match (self.arg_for.take(), byte) {
+            // A command waiting for its argument still is.
+            (arg_for, Cmd::RESEND) => {
+                self.arg_for = arg_for;
+                return XtBytes::one(self.last);
+            }
+            (Some(Cmd::SCAN_CODE_SET), 0) => XtBytes::two(Self::ACK, Self::SCAN_CODE_SET),
+            // Set 2 is all there is, and LED and typematic settings are dropped.
+            (Some(Cmd::SET_LEDS | Cmd::SET_TYPEMATIC | Cmd::SCAN_CODE_SET), _) if is_arg => {
+                XtBytes::one(Self::ACK)
+            }
+            (_, Cmd::RESET) => {
+                *self = Device::new();
+                XtBytes::two(Self::ACK, Self::SELF_TEST_PASSED)
+            }
+            (_, Cmd::ECHO) => XtBytes::one(Self::ECHO),
+            (_, Cmd::IDENTIFY) => XtBytes::three(Self::ACK, Self::ID[0], Self::ID[1]),
+            (_, Cmd::SET_LEDS | Cmd::SET_TYPEMATIC | Cmd::SCAN_CODE_SET) => {
+                self.arg_for = Some(byte);
+                XtBytes::one(Self::ACK)
+            }
+            (_, Cmd::ENABLE) => {
+                self.enabled = true;
+                XtBytes::one(Self::ACK)
+            }
+            (_, Cmd::DISABLE) => {
+                self.enabled = false;
+                XtBytes::one(Self::ACK)
+            }
+            (_, Cmd::SET_DEFAULT) => XtBytes::one(Self::ACK),
+            _ => XtBytes::one(Self::RESEND),
+        };
+
+        self.reply(bytes)
+    }
+
+    // A Set 1 code from the XT keyboard. Breaks are the make code with the
+    // top bit set; Set 2 sends 0xf0 and the make code instead.
+    pub fn key(&mut self, code: u8) -> XtBytes {
+        if code == Self::XT_OVERRUN {
+            return self.overrun();
+        }
+
+        let make = code & !Self::XT_BREAK;
+        let pressed = code == make;
+
+        // The XT keyboard's 0xAA after power-on looks just like a Left Shift
+        // break.
+        if make == Self::XT_LSHIFT {
+            if !pressed && !self.lshift {
+                return XtBytes::none();
+            }
+
+            self.lshift = pressed;
+        }
+
+        if !self.enabled {
+            return XtBytes::none();
+        }
+
+        let bytes = match keymap::from_xt(make) {
+            Some(k) if pressed => XtBytes::one(k),
+            Some(k) => XtBytes::two(Self::BREAK, k),
+            None => XtBytes::none(),
+        };
+
+        self.reply(bytes)
+    }
+
+    fn reply(&mut self, bytes: XtBytes) -> XtBytes {
+        if let Some(b) = bytes.clone().last() {
+            self.last = b;
+        }
+
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keys come out as (key, pressed, extended).
+    fn keys(bytes: &[u8]) -> Vec<(u8, bool, bool)> {
+        let mut decoder = Decoder::new();
+
+        bytes
+            .iter()
+            .filter_map(|&b| match decoder.feed(b) {
+                Some(Decoded::Key(k)) => Some((k.key, k.pressed, k.extended)),
+                Some(_) => panic!("{:#04x} didn't decode to a key", b),
+                None => None,
+            })
+            .collect()
+    }
+
+    fn last(bytes: &[u8]) -> Option<Decoded> {
+        let mut decoder = Decoder::new();
+
+        bytes.iter().fold(None, |_, &b| decoder.feed(b))
+    }
+
+    #[test]
+    fn plain_keys_make_and_break() {
+        assert_eq!(
+            keys(&[0x1c, 0xf0, 0x1c]),
+            [(0x1c, true, false), (0x1c, false, false)]
+        );
+    }
+
+    #[test]
+    fn extended_keys_make_and_break() {
+        assert_eq!(
+            keys(&[0xe0, 0x75, 0xe0, 0xf0, 0x75]),
+            [(0x75, true, true), (0x75, false, true)]
+        );
+    }
+
+    #[test]
+    fn pause_makes_and_breaks_in_one_sequence() {
+        assert_eq!(
+            keys(&Decoder::PAUSE_SEQ),
+            [
+                (KeyEvent::PAUSE, true, true),
+                (KeyEvent::PAUSE, false, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn acks_and_echoes_are_ignored() {
+        assert_eq!(keys(&[0xfa, 0xee, 0xfe, 0x1c]), [(0x1c, true, false)]);
+    }
+
+    #[test]
+    fn self_test_means_attached() {
+        assert!(matches!(last(&[0xaa]), Some(Decoded::Attached)));
+    }
+
+    #[test]
+    fn nonsense_after_a_prefix_desyncs() {
+        assert!(matches!(last(&[0xf0, 0xe0]), Some(Decoded::Desync)));
+        assert!(matches!(last(&[0xe0, 0xaa]), Some(Decoded::Desync)));
+        assert!(matches!(last(&[0xe0, 0xf0, 0xfa]), Some(Decoded::Desync)));
+        assert!(matches!(last(&[0xe1, 0x14, 0x1c]), Some(Decoded::Desync)));
+    }
+
+    #[test]
+    fn a_desync_starts_over() {
+        let mut decoder = Decoder::new();
+
+        assert!(decoder.feed(0xe1).is_none());
+        assert!(matches!(decoder.feed(0x1c), Some(Decoded::Desync)));
+        assert!(matches!(decoder.feed(0x1c), Some(Decoded::Key(k)) if k.pressed));
+    }
+
+    // What the device sends back to each of the host's commands.
+    #[cfg(feature = "xt-keyboard")]
+    fn replies(device: &mut Device, cmds: &[u8]) -> Vec<Vec<u8>> {
+        cmds.iter().map(|&c| device.command(c).collect()).collect()
+    }
+
+    // What the device sends the host for each Set 1 code.
+    #[cfg(feature = "xt-keyboard")]
+    fn typed(device: &mut Device, codes: &[u8]) -> Vec<Vec<u8>> {
+        codes.iter().map(|&c| device.key(c).collect()).collect()
+    }
+
+    #[cfg(feature = "xt-keyboard")]
+    #[test]
+    fn commands_take_their_arguments() {
+        let mut device = Device::new();
+
+        assert_eq!(
+            replies(&mut device, &[0xed, 0x07, 0xf3, 0x2b]),
+            [[0xfa], [0xfa], [0xfa], [0xfa]]
+        );
+        // An argument without a command is nonsense.
+        assert_eq!(replies(&mut device, &[0x07]), [[0xfe]]);
+    }
+
+    #[cfg(feature = "xt-keyboard")]
+    #[test]
+    fn a_command_cancels_one_waiting_for_its_argument() {
+        let mut device = Device::new();
+
+        assert_eq!(
+            replies(&mut device, &[0xed, 0xee, 0x07]),
+            [[0xfa], [0xee], [0xfe]]
+        );
+    }
+
+    #[cfg(feature = "xt-keyboard")]
+    #[test]
+    fn resend_repeats_the_last_byte() {
+        let mut device = Device::new();
+
+        assert_eq!(replies(&mut device, &[0xfe]), [[0xaa]]);
+        assert_eq!(replies(&mut device, &[0xee, 0xfe]), [[0xee], [0xee]]);
+        assert_eq!(typed(&mut device, &[0x9e]), [[0xf0, 0x1c]]);
+        assert_eq!(replies(&mut device, &[0xfe]), [[0x1c]]);
+    }
+
+    #[cfg(feature = "xt-keyboard")]
+    #[test]
+    fn resend_keeps_a_command_waiting_for_its_argument() {
+        let mut device = Device::new();
+
+        assert_eq!(
+            replies(&mut device, &[0xed, 0xfe, 0x07]),
+            [[0xfa], [0xfa], [0xfa]]
+        );
+    }
+
+    #[cfg(feature = "xt-keyboard")]
+    #[test]
+    fn identify_and_scan_code_set() {
+        let mut device = Device::new();
+
+        assert_eq!(replies(&mut device, &[0xf2]), [[0xfa, 0xab, 0x83]]);
+        assert_eq!(
+            replies(&mut device, &[0xf0, 0x00]),
+            [vec![0xfa], vec![0xfa, 0x02]]
+        );
+        // Asking for another set is ACKed, and changes nothing.
+        assert_eq!(
+            replies(&mut device, &[0xf0, 0x01, 0xf0, 0x00]),
+            [vec![0xfa], vec![0xfa], vec![0xfa], vec![0xfa, 0x02]]
+        );
+    }
+
+    #[cfg(feature = "xt-keyboard")]
+    #[test]
+    fn set_1_codes_become_set_2() {
+        let mut device = Device::new();
+
+        // A, Enter.
+        assert_eq!(
+            typed(&mut device, &[0x1e, 0x9e, 0x1c, 0x9c]),
+            [vec![0x1c], vec![0xf0, 0x1c], vec![0x5a], vec![0xf0, 0x5a]]
+        );
+    }
+
+    #[cfg(feature = "xt-keyboard")]
+    #[test]
+    fn power_on_0xaa_isnt_a_left_shift_break() {
+        let mut device = Device::new();
+
+        assert_eq!(typed(&mut device, &[0xaa]), [[]]);
+        assert_eq!(
+            typed(&mut device, &[0x2a, 0xaa, 0xaa]),
+            [vec![0x12], vec![0xf0, 0x12], vec![]]
+        );
+    }
+
+    #[cfg(feature = "xt-keyboard")]
+    #[test]
+    fn overrun_is_0x00() {
+        let mut device = Device::new();
+
+        assert_eq!(typed(&mut device, &[0xff]), [[0x00]]);
+    }
+
+    #[cfg(feature = "xt-keyboard")]
+    #[test]
+    fn a_disabled_device_sends_no_keys() {
+        let mut device = Device::new();
+
+        assert_eq!(replies(&mut device, &[0xf5]), [[0xfa]]);
+        assert_eq!(typed(&mut device, &[0x1e]), [[]]);
+        assert_eq!(replies(&mut device, &[0xf4]), [[0xfa]]);
+        assert_eq!(typed(&mut device, &[0x1e]), [[0x1c]]);
+    }
+}
@@ -0,0 +1,464 @@
+use crate::keymap;
+#[cfg(feature = "special-keys")]
+use crate::keymap::Action;
+use crate::keystate::KeyEvent;
+
+// How bytes are clocked out to the host, in 10us timer ticks.
+pub struct XtTiming {
+    pub clk_low: u16,
+    pub clk_high: u16,
+    pub two_start_bits: bool, // IBM sends a 0 then a 1; some clones want just the 1.
+    pub byte_gap: u16,        // Extra idle time before each byte.
+}
+
+#[allow(dead_code)]
+impl XtTiming {
+    pub const IBM: XtTiming = XtTiming {
+        clk_low: 6,
+        clk_high: 4,
+        two_start_bits: true,
+        byte_gap: 0,
+    };
+
+    // Tandy, Amstrad, Olivetti, and Turbo XT boards that miss bytes.
+    pub const SLOW: XtTiming = XtTiming {
+        clk_low: 9,
+        clk_high: 6,
+        two_start_bits: true,
+        byte_gap: 50,
+    };
+
+    // Hosts that sample early and see a long pulse as two.
+    pub const FAST: XtTiming = XtTiming {
+        clk_low: 4,
+        clk_high: 3,
+        two_start_bits: true,
+        byte_gap: 0,
+    };
+
+    pub const ONE_START_BIT: XtTiming = XtTiming {
+        two_start_bits: false,
+        ..XtTiming::SLOW
+    };
+}
+
+#[cfg(any(
+    all(feature = "xt-timing-slow", feature = "xt-timing-fast"),
+    all(feature = "xt-timing-slow", feature = "xt-timing-one-start-bit"),
+    all(feature = "xt-timing-fast", feature = "xt-timing-one-start-bit"),
+))]
+compile_error!("Select at most one xt-timing-* feature.");
+
+#[cfg(feature = "xt-timing-slow")]
+pub const TIMING: XtTiming = XtTiming::SLOW;
+#[cfg(feature = "xt-timing-fast")]
+pub const TIMING: XtTiming = XtTiming::FAST;
+#[cfg(feature = "xt-timing-one-start-bit")]
+pub const TIMING: XtTiming = XtTiming::ONE_START_BIT;
+#[cfg(not(any(
+    feature = "xt-timing-slow",
+    feature = "xt-timing-fast",
+    feature = "xt-timing-one-start-bit"
+)))]
+pub const TIMING: XtTiming = XtTiming::IBM;
+
+// XT make codes the host has been sent without a matching break code.
+struct KeysDown {
+    bits: [u8; 16],
+}
+
+impl KeysDown {
+    const fn new() -> KeysDown {
+        KeysDown { bits: [0; 16] }
+    }
+
+    fn update(&mut self, xt_key: u8) {
+        let code = xt_key & 0x7f;
+
+        if let Some(b) = self.bits.get_mut(usize::from(code >> 3)) {
+            if xt_key & 0x80 == 0 {
+                *b |= 1 << (code & 0x07);
+            } else {
+                *b &= !(1 << (code & 0x07));
+            }
+        }
+    }
+
+    // Forgets one of the keys, and returns its break code.
+    fn release(&mut self) -> Option<u8> {
+        self.first().map(|k| {
+            self.update(k | Encoder::BREAK);
+            k | Encoder::BREAK
+        })
+    }
+
+    fn first(&self) -> Option<u8> {
+        (0u8..)
+            .zip(self.bits.iter())
+            .find(|(_, b)| **b != 0)
+            .map(|(i, b)| {
+                let mut bit = 0;
+                while b & (1 << bit) == 0 {
+                    bit += 1;
+                }
+                (i << 3) | bit
+            })
+    }
+}
+
+// The bytes that make up a single key event on the XT side.
+#[derive(Clone)]
+pub enum XtBytes {
+    Seq {
+        bytes: [u8; 3],
+        len: u8,
+        pos: u8,
+    },
+    #[cfg(feature = "special-keys")]
+    Chord {
+        keys: &'static [u8],
+        step: u8,
+    }, // Makes in order, then breaks in reverse.
+    #[cfg(feature = "ascii")]
+    Text {
+        bytes: &'static [u8],
+        pos: u8,
+    },
+}
+
+impl XtBytes {
+    pub fn none() -> XtBytes {
+        XtBytes::Seq {
+            bytes: [0; 3],
+            len: 0,
+            pos: 0,
+        }
+    }
+
+    #[cfg(feature = "ascii")]
+    pub fn text(bytes: &'static [u8]) -> XtBytes {
+        XtBytes::Text { bytes, pos: 0 }
+    }
+
+    pub fn one(b: u8) -> XtBytes {
+        XtBytes::Seq {
+            bytes: [b, 0, 0],
+            len: 1,
+            pos: 0,
+        }
+    }
+
+    #[cfg_attr(feature = "tandy1000", allow(dead_code))]
+    pub fn two(b0: u8, b1: u8) -> XtBytes {
+        XtBytes::Seq {
+            bytes: [b0, b1, 0],
+            len: 2,
+            pos: 0,
+        }
+    }
+
+    #[cfg_attr(any(feature = "tandy1000", feature = "pcjr"), allow(dead_code))]
+    pub fn three(b0: u8, b1: u8, b2: u8) -> XtBytes {
+        XtBytes::Seq {
+            bytes: [b0, b1, b2],
+            len: 3,
+            pos: 0,
+        }
+    }
+}
+
+impl Iterator for XtBytes {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        match self {
+            XtBytes::Seq { bytes, len, pos } => {
+                if *pos < *len {
+                    let b = bytes.get(usize::from(*pos)).copied();
+                    *pos += 1;
+                    b
+                } else {
+                    None
+                }
+            }
+            #[cfg(feature = "ascii")]
+            XtBytes::Text { bytes, pos } => {
+                let b = bytes.get(usize::from(*pos)).copied();
+                *pos = pos.saturating_add(1);
+                b
+            }
+            #[cfg(feature = "special-keys")]
+            XtBytes::Chord { keys, step } => {
+                let i = usize::from(*step);
+                let len = keys.len();
+                *step += 1;
+
+                if i < len {
+                    keys.get(i).copied()
+                } else {
+                    (2 * len)
+                        .checked_sub(i + 1)
+                        .and_then(|i| keys.get(i))
+                        .map(|k| k | Encoder::BREAK)
+                }
+            }
+        }
+    }
+}
+
+// Sends a break code for every key the host thinks is held down.
+pub struct ReleaseAll<'a> {
+    keys_down: &'a mut KeysDown,
+    #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+    extended_down: &'a mut KeysDown,
+    #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+    pending: Option<u8>, // Break code to send after its E0.
+}
+
+impl Iterator for ReleaseAll<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+        if let Some(b) = self.pending.take() {
+            return Some(b);
+        }
+
+        #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+        if let Some(b) = self.extended_down.release() {
+            self.pending = Some(b);
+            return Some(Encoder::PREFIX);
+        }
+
+        self.keys_down.release()
+    }
+}
+
+pub struct Encoder {
+    keys_down: KeysDown,
+    // E0-prefixed keys; their codes overlap the others'.
+    #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+    extended_down: KeysDown,
+}
+
+impl Encoder {
+    #[cfg_attr(any(feature = "tandy1000", feature = "pcjr"), allow(dead_code))]
+    pub const SELF_TEST_PASSED: u8 = 0xaa;
+    pub const OVERRUN: u8 = 0xff;
+    const BREAK: u8 = 0x80;
+    #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+    const PREFIX: u8 = 0xe0;
+    #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+    const PREFIX_PAUSE: u8 = 0xe1;
+    #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+    const CTRL: u8 = 0x1d;
+    #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+    const NUM: u8 = 0x45;
+    #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+    const LSHIFT: u8 = 0x2a;
+    #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+    const RSHIFT: u8 = 0x36;
+    #[cfg(feature = "tandy1000")]
+    const HOLD: u8 = 0x46;
+    #[cfg(feature = "pcjr")]
+    const FN: u8 = 0x54;
+    #[cfg(feature = "pcjr")]
+    const FN_PAUSE: u8 = 0x10; // Q
+
+    // Most bytes a single key event sends: Pause, or the makes and breaks
+    // of Ctrl+Alt+Del.
+    #[cfg(not(feature = "power-ctrl-alt-del"))]
+    pub const LONGEST: u8 = 3;
+    #[cfg(feature = "power-ctrl-alt-del")]
+    pub const LONGEST: u8 = 6;
+
+    pub const fn new() -> Encoder {
+        Encoder {
+            keys_down: KeysDown::new(),
+            #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+            extended_down: KeysDown::new(),
+        }
+    }
+
+    pub fn encode(&mut self, ev: KeyEvent) -> XtBytes {
+        let brk = if ev.pressed { 0 } else { Self::BREAK };
+
+        if ev.is_pause() {
+            return self.pause(brk);
+        }
+
+        #[cfg(feature = "special-keys")]
+        if ev.extended {
+            if let Some(action) = keymap::special(ev.key) {
+                return self.special(action, brk);
+            }
+        }
+
+        self.key(ev, brk)
+    }
+
+    #[cfg(feature = "special-keys")]
+    fn special(&mut self, action: Action, brk: u8) -> XtBytes {
+        match action {
+            Action::Key(x) => {
+                self.keys_down.update(x | brk);
+                XtBytes::one(x | brk)
+            }
+            Action::Chord(keys) if brk == 0 => XtBytes::Chord { keys, step: 0 },
+            // Chords finish on press. Functions are up to the caller.
+            Action::Drop | Action::Chord(_) | Action::Function(_) => XtBytes::none(),
+        }
+    }
+
+    #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+    fn pause(&mut self, brk: u8) -> XtBytes {
+        XtBytes::three(Self::PREFIX_PAUSE, Self::CTRL | brk, Self::NUM | brk)
+    }
+
+    // Hold is the closest thing a Tandy 1000 has to Pause.
+    #[cfg(feature = "tandy1000")]
+    fn pause(&mut self, brk: u8) -> XtBytes {
+        self.keys_down.update(Self::HOLD | brk);
+        XtBytes::one(Self::HOLD | brk)
+    }
+
+    #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+    fn key(&mut self, ev: KeyEvent, brk: u8) -> XtBytes {
+        keymap::to_xt(ev.key).map_or(XtBytes::none(), |x| {
+            if !ev.extended {
+                self.keys_down.update(x | brk);
+                return XtBytes::one(x | brk);
+            }
+
+            // E0 2A and E0 36 are the fake shifts some keys add, not keys of
+            // their own.
+            if x != Self::LSHIFT && x != Self::RSHIFT {
+                self.extended_down.update(x | brk);
+            }
+            XtBytes::two(Self::PREFIX, x | brk)
+        })
+    }
+
+    #[cfg(feature = "tandy1000")]
+    fn key(&mut self, ev: KeyEvent, brk: u8) -> XtBytes {
+        keymap::to_tandy(ev.key, ev.extended).map_or(XtBytes::none(), |x| {
+            self.keys_down.update(x | brk);
+            XtBytes::one(x | brk)
+        })
+    }
+
+    #[cfg(feature = "pcjr")]
+    fn pause(&mut self, brk: u8) -> XtBytes {
+        self.fn_key(Self::FN_PAUSE, brk)
+    }
+
+    #[cfg(feature = "pcjr")]
+    fn key(&mut self, ev: KeyEvent, brk: u8) -> XtBytes {
+        match keymap::to_pcjr(ev.key, ev.extended) {
+            Some((x, true)) => self.fn_key(x, brk),
+            Some((x, false)) => {
+                self.keys_down.update(x | brk);
+                XtBytes::one(x | brk)
+            }
+            None => XtBytes::none(),
+        }
+    }
+
+    // Fn goes down before the key and comes up after it.
+    #[cfg(feature = "pcjr")]
+    fn fn_key(&mut self, x: u8, brk: u8) -> XtBytes {
+        self.keys_down.update(x | brk);
+
+        if brk == 0 {
+            XtBytes::two(Self::FN, x)
+        } else {
+            XtBytes::two(x | brk, Self::FN | brk)
+        }
+    }
+
+    // The host was reset and forgot every key on its own.
+    pub fn reset(&mut self) {
+        *self = Encoder::new();
+    }
+
+    pub fn release_all(&mut self) -> ReleaseAll<'_> {
+        ReleaseAll {
+            keys_down: &mut self.keys_down,
+            #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+            extended_down: &mut self.extended_down,
+            #[cfg(not(any(feature = "tandy1000", feature = "pcjr")))]
+            pending: None,
+        }
+    }
+}
+
+#[cfg(all(test, not(any(feature = "tandy1000", feature = "pcjr"))))]
+mod tests {
+    use super::*;
+
+    // Bytes sent to the host for (Set 2 key, pressed, extended).
+    fn type_keys(encoder: &mut Encoder, keys: &[(u8, bool, bool)]) -> Vec<u8> {
+        keys.iter()
+            .flat_map(|&(key, pressed, extended)| {
+                encoder.encode(KeyEvent {
+                    key,
+                    pressed,
+                    extended,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn no_key_sends_more_than_longest() {
+        for key in 0..=0xff {
+            for extended in [false, true] {
+                for pressed in [true, false] {
+                    let n = type_keys(&mut Encoder::new(), &[(key, pressed, extended)]).len();
+                    assert!(n <= usize::from(Encoder::LONGEST), "{:#x} sent {}", key, n);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn extended_keys_are_released_with_e0() {
+        let mut encoder = Encoder::new();
+
+        // Right Ctrl.
+        assert_eq!(type_keys(&mut encoder, &[(0x14, true, true)]), [0xe0, 0x1d]);
+        assert_eq!(encoder.release_all().collect::<Vec<_>>(), [0xe0, 0x9d]);
+        assert_eq!(encoder.release_all().count(), 0);
+    }
+
+    #[test]
+    fn extended_keys_dont_share_codes_with_the_others() {
+        let mut encoder = Encoder::new();
+
+        // Left Ctrl down, then Right Ctrl down and up.
+        type_keys(
+            &mut encoder,
+            &[(0x14, true, false), (0x14, true, true), (0x14, false, true)],
+        );
+        assert_eq!(encoder.release_all().collect::<Vec<_>>(), [0x9d]);
+    }
+
+    #[test]
+    fn fake_shifts_are_not_held() {
+        let mut encoder = Encoder::new();
+
+        // Print Screen with its fake Left Shift (E0 2A) still down.
+        let sent = type_keys(&mut encoder, &[(0x12, true, true), (0x7c, true, true)]);
+        assert_eq!(sent, [0xe0, 0x2a, 0xe0, 0x37]);
+        assert_eq!(encoder.release_all().collect::<Vec<_>>(), [0xe0, 0xb7]);
+    }
+
+    #[test]
+    fn fake_shift_breaks_dont_release_real_shifts() {
+        let mut encoder = Encoder::new();
+
+        // Left Shift held while the fake one (E0 AA) comes up.
+        type_keys(&mut encoder, &[(0x12, true, false), (0x12, false, true)]);
+        assert_eq!(encoder.release_all().collect::<Vec<_>>(), [0xaa]);
+    }
+}